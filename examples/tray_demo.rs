@@ -149,8 +149,13 @@ fn build_tray_state(app_state: &AppState) -> TrayState {
         .submenu(TrayMenuItem::radio("List", "List", list_checked))
         .submenu(TrayMenuItem::radio("Grid", "Grid", grid_checked))
         .submenu(TrayMenuItem::separator())
-        .submenu(TrayMenuItem::menu("HideWindow", "Hide Window", Vec::new()))
-        .submenu(TrayMenuItem::menu("ShowWindow", "Show Window", Vec::new()).enabled(!list_checked))
+        .submenu(TrayMenuItem::action("Hide Window", |cx| {
+            hide_window(&HideWindow, cx)
+        }))
+        .submenu(
+            TrayMenuItem::action("Show Window", |cx| show_window(&ShowWindow, cx))
+                .enabled(!list_checked),
+        )
         .submenu(TrayMenuItem::separator())
         .submenu(TrayMenuItem::menu(
             "ToggleVisible",
@@ -169,7 +174,7 @@ fn build_tray_state(app_state: &AppState) -> TrayState {
             .visible(grid_checked),
         )
         .submenu(TrayMenuItem::separator())
-        .submenu(TrayMenuItem::menu("Quit", "Quit", Vec::new()))
+        .submenu(TrayMenuItem::action("Quit", |cx| quit(&Quit, cx)))
 }
 
 fn refresh_tray(cx: &mut App) {
@@ -246,7 +251,7 @@ fn on_tray_event(event: TrayEvent, cx: &mut App) {
                 show_window(&ShowWindow, cx);
             }
         }
-        TrayEvent::MenuClick { id } => match id.as_str() {
+        TrayEvent::MenuClick { id, .. } => match id.as_str() {
             "List" => {
                 let current_is_list = cx.global::<AppState>().view_mode == ViewMode::List;
                 if !current_is_list {
@@ -261,9 +266,6 @@ fn on_tray_event(event: TrayEvent, cx: &mut App) {
             }
             "SubToggleCheck" => toggle_check(&ToggleCheck, cx),
             "ToggleVisible" | "SubToggleVisible" => toggle_visible(&ToggleVisible, cx),
-            "HideWindow" => hide_window(&HideWindow, cx),
-            "ShowWindow" => show_window(&ShowWindow, cx),
-            "Quit" => quit(&Quit, cx),
             _ => {}
         },
         _ => {}
@@ -21,3 +21,100 @@ pub(crate) fn decode_gpui_image_to_bgra32(image: &gpui::Image) -> Result<(u32, u
 
     Ok((size.width.0 as u32, size.height.0 as u32, bytes.to_vec()))
 }
+
+/// Scale a BGRA8 buffer with nearest-neighbor sampling.
+pub(crate) fn resize_bgra32_nearest(
+    src: &[u8],
+    src_w: u32,
+    src_h: u32,
+    dst_w: u32,
+    dst_h: u32,
+) -> Result<Vec<u8>> {
+    anyhow::ensure!(
+        src_w > 0 && src_h > 0 && dst_w > 0 && dst_h > 0,
+        "invalid size"
+    );
+    let src_w = src_w as usize;
+    let src_h = src_h as usize;
+    let dst_w = dst_w as usize;
+    let dst_h = dst_h as usize;
+    anyhow::ensure!(
+        src.len() == src_w * src_h * 4,
+        "expected BGRA32 buffer length {}",
+        src_w * src_h * 4
+    );
+
+    let mut dst = vec![0u8; dst_w * dst_h * 4];
+    for y in 0..dst_h {
+        let sy = y * src_h / dst_h;
+        for x in 0..dst_w {
+            let sx = x * src_w / dst_w;
+            let s = (sy * src_w + sx) * 4;
+            let d = (y * dst_w + x) * 4;
+            dst[d..d + 4].copy_from_slice(&src[s..s + 4]);
+        }
+    }
+    Ok(dst)
+}
+
+/// Largest size of at most `max` x `max` with the aspect ratio of `width` x `height`.
+#[cfg_attr(not(windows), allow(dead_code))]
+pub(crate) fn fit_within(width: u32, height: u32, max: u32) -> (u32, u32) {
+    let longest = width.max(height);
+    if longest <= max {
+        return (width, height);
+    }
+    let scale = |side: u32| ((side as u64 * max as u64 / longest as u64) as u32).max(1);
+    (scale(width), scale(height))
+}
+
+/// Encode a `gpui::Image` as PNG bytes.
+///
+/// PNG input is passed through untouched; other formats are decoded with
+/// [`decode_gpui_image_to_bgra32`] and re-encoded.
+#[cfg(target_os = "linux")]
+pub(crate) fn encode_gpui_image_to_png(image: &gpui::Image) -> Result<Vec<u8>> {
+    if image.format == gpui::ImageFormat::Png {
+        return Ok(image.bytes.clone());
+    }
+
+    let (width, height, mut pixels) = decode_gpui_image_to_bgra32(image)?;
+    for pixel in pixels.chunks_exact_mut(4) {
+        pixel.swap(0, 2);
+    }
+    encode_rgba32_to_png(width, height, &pixels)
+}
+
+#[cfg(target_os = "linux")]
+fn encode_rgba32_to_png(width: u32, height: u32, rgba: &[u8]) -> Result<Vec<u8>> {
+    use image::ImageEncoder as _;
+
+    let mut png = Vec::new();
+    image::codecs::png::PngEncoder::new(&mut png)
+        .write_image(rgba, width, height, image::ExtendedColorType::Rgba8)
+        .context("failed to encode PNG")?;
+    Ok(png)
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::{encode_rgba32_to_png, fit_within};
+
+    #[test]
+    fn png_encoding_writes_header_and_chunks() {
+        let png = encode_rgba32_to_png(2, 1, &[255, 0, 0, 255, 0, 255, 0, 128]).unwrap();
+
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[16..24], &[0, 0, 0, 2, 0, 0, 0, 1]);
+        assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+    }
+
+    #[test]
+    fn fit_within_keeps_aspect_ratio() {
+        assert_eq!(fit_within(12, 10, 16), (12, 10));
+        assert_eq!(fit_within(32, 8, 16), (16, 4));
+        assert_eq!(fit_within(10, 40, 16), (4, 16));
+        assert_eq!(fit_within(1000, 1, 16), (16, 1));
+    }
+}
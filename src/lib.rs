@@ -3,6 +3,7 @@ mod icon;
 pub mod tray;
 
 pub use tray::{
-    TrayClickAction, TrayClickKind, TrayClickPolicy, TrayEvent, TrayHandle, TrayMenuItem,
-    TrayMenuItemRole, TrayState, TrayToggleType,
+    TrayClickAction, TrayClickKind, TrayClickPolicy, TrayEvent, TrayHandle, TrayMenuAction,
+    TrayMenuCallback, TrayMenuIcon, TrayMenuItem, TrayMenuItemRole, TrayMenuPayload,
+    TrayMenuProvider, TrayState, TrayStateDiff, TrayToggleType,
 };
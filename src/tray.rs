@@ -1,24 +1,204 @@
-use gpui::{App, AsyncApp, Image, MouseButton, Point};
-use std::rc::Rc;
+use gpui::{Action, App, AsyncApp, Image, Keystroke, MouseButton, Point};
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 pub(crate) type TrayEventCallback = Box<dyn FnMut(TrayEvent, &mut App) + Send + 'static>;
 pub(crate) type TrayEventCallbackSlot = Arc<Mutex<Option<TrayEventCallback>>>;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum TrayToggleType {
     Checkbox(bool),
+    /// A checkbox in the "mixed" state, e.g. for an entry summarizing partially enabled options.
+    Indeterminate,
     Radio(bool),
+    /// A radio entry in a named group. At most one entry per group is checked; groups may span
+    /// submenus.
+    GroupedRadio {
+        group: String,
+        checked: bool,
+    },
+}
+
+impl TrayToggleType {
+    pub fn is_checked(&self) -> bool {
+        match self {
+            Self::Checkbox(checked) | Self::Radio(checked) | Self::GroupedRadio { checked, .. } => {
+                *checked
+            }
+            Self::Indeterminate => false,
+        }
+    }
+
+    pub fn is_radio(&self) -> bool {
+        matches!(self, Self::Radio(_) | Self::GroupedRadio { .. })
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum TrayMenuItemRole {
     Standard,
+    /// Non-interactive text row, see [`TrayMenuItem::info`].
     Info,
+    /// Clickable entry styled as informational (dbusmenu `disposition` "informative").
+    Informative,
+    /// Clickable entry styled as a warning (dbusmenu `disposition` "warning").
+    Warning,
+    /// Clickable entry styled as an alert (dbusmenu `disposition` "alert").
+    Alert,
+}
+
+impl TrayMenuItemRole {
+    /// The dbusmenu `disposition` for this role, or `None` for the default "normal".
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    pub(crate) fn disposition(self) -> Option<&'static str> {
+        match self {
+            Self::Standard | Self::Info => None,
+            Self::Informative => Some("informative"),
+            Self::Warning => Some("warning"),
+            Self::Alert => Some("alert"),
+        }
+    }
+}
+
+/// Icon shown next to a tray menu entry.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TrayMenuIcon {
+    Image(Arc<Image>),
+    /// Freedesktop icon theme name (e.g. `document-open`). Only honored on Linux.
+    Named(String),
+}
+
+/// Opaque value attached to a menu item and handed back in [`TrayEvent::MenuClick`].
+///
+/// Attaching a user-defined enum lets apps dispatch clicks with `match` instead of comparing
+/// string IDs.
+#[derive(Clone)]
+pub struct TrayMenuPayload(Arc<dyn Any + Send + Sync>);
+
+impl TrayMenuPayload {
+    pub fn new(value: impl Any + Send + Sync) -> Self {
+        Self(Arc::new(value))
+    }
+
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        self.0.downcast_ref()
+    }
+}
+
+// Payloads, callbacks and providers are opaque, so two of them are equal only if they are the
+// same allocation, i.e. clones of each other.
+impl PartialEq for TrayMenuPayload {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl fmt::Debug for TrayMenuPayload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TrayMenuPayload(..)")
+    }
+}
+
+/// Closure run on the main thread when a menu entry is clicked.
+#[derive(Clone)]
+pub struct TrayMenuCallback(Arc<dyn Fn(&mut App) + Send + Sync + 'static>);
+
+impl TrayMenuCallback {
+    pub fn new(callback: impl Fn(&mut App) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(callback))
+    }
+}
+
+impl PartialEq for TrayMenuCallback {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl fmt::Debug for TrayMenuCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TrayMenuCallback(..)")
+    }
+}
+
+/// Closure run on the main thread to build the children of a lazily populated submenu.
+#[derive(Clone)]
+pub struct TrayMenuProvider(Arc<TrayMenuProviderFn>);
+
+type TrayMenuProviderFn = dyn Fn(&mut App) -> Vec<TrayMenuItem> + Send + Sync + 'static;
+
+impl TrayMenuProvider {
+    pub fn new(provider: impl Fn(&mut App) -> Vec<TrayMenuItem> + Send + Sync + 'static) -> Self {
+        Self(Arc::new(provider))
+    }
+
+    /// Builds the children of the submenu. Lazy submenus among them are left empty; backends
+    /// that can't populate them on demand use [`TrayState::populate_lazy_submenus`].
+    pub(crate) fn populate(&self, cx: &mut App) -> Vec<TrayMenuItem> {
+        let mut items = (self.0)(cx);
+        resolve_action_availability(&mut items, cx);
+        items
+    }
+}
+
+impl PartialEq for TrayMenuProvider {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl fmt::Debug for TrayMenuProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TrayMenuProvider(..)")
+    }
+}
+
+/// gpui [`Action`] dispatched through [`App::dispatch_action`] when a menu entry is clicked.
+pub struct TrayMenuAction {
+    action: Box<dyn Action>,
+    auto_disable: bool,
+}
+
+impl TrayMenuAction {
+    pub fn new(action: Box<dyn Action>) -> Self {
+        Self {
+            action,
+            auto_disable: false,
+        }
+    }
+}
+
+impl Clone for TrayMenuAction {
+    fn clone(&self) -> Self {
+        Self {
+            action: self.action.boxed_clone(),
+            auto_disable: self.auto_disable,
+        }
+    }
+}
+
+impl PartialEq for TrayMenuAction {
+    fn eq(&self, other: &Self) -> bool {
+        self.action.partial_eq(other.action.as_ref()) && self.auto_disable == other.auto_disable
+    }
+}
+
+impl fmt::Debug for TrayMenuAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TrayMenuAction")
+            .field("action", &self.action.name())
+            .field("auto_disable", &self.auto_disable)
+            .finish()
+    }
 }
 
 /// Item used to describe a tray context menu.
-#[derive(Clone, Debug)]
+// Separators are rare next to regular entries, so boxing the large variant wouldn't save memory.
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum TrayMenuItem {
     Separator {
         label: Option<String>,
@@ -31,6 +211,13 @@ pub enum TrayMenuItem {
         visible: bool,
         role: TrayMenuItemRole,
         toggle_type: Option<TrayToggleType>,
+        icon: Option<TrayMenuIcon>,
+        shortcut: Vec<Keystroke>,
+        mnemonic: Option<char>,
+        payload: Option<TrayMenuPayload>,
+        on_click: Option<TrayMenuCallback>,
+        action: Option<TrayMenuAction>,
+        provider: Option<TrayMenuProvider>,
         children: Vec<TrayMenuItem>,
     },
 }
@@ -43,6 +230,7 @@ impl TrayMenuItem {
         }
     }
 
+    /// A separator followed by a disabled header row showing `label`.
     pub fn labeled_separator(label: impl Into<String>) -> Self {
         Self::Separator {
             label: Some(label.into()),
@@ -50,44 +238,87 @@ impl TrayMenuItem {
         }
     }
 
+    /// Groups `items` under a section header: a [`TrayMenuItem::labeled_separator`] followed by
+    /// the items. Pass the result to [`TrayState::submenus`] or extend a children list with it.
+    pub fn section(
+        label: impl Into<String>,
+        items: impl IntoIterator<Item = TrayMenuItem>,
+    ) -> Vec<TrayMenuItem> {
+        std::iter::once(Self::labeled_separator(label))
+            .chain(items)
+            .collect()
+    }
+
     pub fn menu(
         id: impl Into<String>,
         label: impl Into<String>,
         children: Vec<TrayMenuItem>,
     ) -> Self {
-        Self::Submenu {
-            id: Some(id.into()),
-            label: label.into(),
-            enabled: true,
-            visible: true,
-            role: TrayMenuItemRole::Standard,
-            toggle_type: None,
-            children,
-        }
+        Self::entry(Some(id.into()), label).with_children(children)
     }
 
     pub fn checkbox(id: impl Into<String>, label: impl Into<String>, checked: bool) -> Self {
-        Self::Submenu {
-            id: Some(id.into()),
-            label: label.into(),
-            enabled: true,
-            visible: true,
-            role: TrayMenuItemRole::Standard,
-            toggle_type: Some(TrayToggleType::Checkbox(checked)),
-            children: Vec::new(),
-        }
+        Self::entry(Some(id.into()), label).with_toggle(TrayToggleType::Checkbox(checked))
     }
 
     pub fn radio(id: impl Into<String>, label: impl Into<String>, checked: bool) -> Self {
-        Self::Submenu {
-            id: Some(id.into()),
-            label: label.into(),
-            enabled: true,
-            visible: true,
-            role: TrayMenuItemRole::Standard,
-            toggle_type: Some(TrayToggleType::Radio(checked)),
-            children: Vec::new(),
-        }
+        Self::entry(Some(id.into()), label).with_toggle(TrayToggleType::Radio(checked))
+    }
+
+    /// Creates a checkbox in the "mixed" state.
+    pub fn indeterminate(id: impl Into<String>, label: impl Into<String>) -> Self {
+        Self::entry(Some(id.into()), label).with_toggle(TrayToggleType::Indeterminate)
+    }
+
+    /// Creates a radio entry in `group`. Checking one entry of a group unchecks the others, so
+    /// a new state only has to check the selected entry. If several entries become checked at
+    /// once (or in the first state), the first of them in menu order wins.
+    pub fn grouped_radio(
+        group: impl Into<String>,
+        id: impl Into<String>,
+        label: impl Into<String>,
+        checked: bool,
+    ) -> Self {
+        Self::entry(Some(id.into()), label).with_toggle(TrayToggleType::GroupedRadio {
+            group: group.into(),
+            checked,
+        })
+    }
+
+    /// Creates a clickable entry identified by `payload` instead of a string ID.
+    ///
+    /// The click is reported as [`TrayEvent::MenuClick`] with an empty `id`.
+    pub fn typed(label: impl Into<String>, payload: impl Any + Send + Sync) -> Self {
+        Self::entry(None, label).payload(payload)
+    }
+
+    /// Creates an entry that runs `on_click` when clicked, instead of emitting
+    /// [`TrayEvent::MenuClick`].
+    pub fn action(
+        label: impl Into<String>,
+        on_click: impl Fn(&mut App) + Send + Sync + 'static,
+    ) -> Self {
+        Self::entry(None, label).on_click(on_click)
+    }
+
+    /// Creates an entry that dispatches `action` through [`App::dispatch_action`] when clicked,
+    /// like `gpui::MenuItem::action`.
+    pub fn dispatch(label: impl Into<String>, action: impl Action) -> Self {
+        Self::entry(None, label).with_action(TrayMenuAction::new(Box::new(action)))
+    }
+
+    /// Creates a submenu whose children are built by `provider` each time the menu is about
+    /// to be shown, for entries that are expensive to compute up front.
+    ///
+    /// On Linux the provider runs when the host sends `AboutToShow`, and on macOS when the
+    /// submenu is about to open. Windows can't populate a menu while it is shown, so there all
+    /// providers run right before the tray menu opens.
+    pub fn lazy(
+        id: impl Into<String>,
+        label: impl Into<String>,
+        provider: impl Fn(&mut App) -> Vec<TrayMenuItem> + Send + Sync + 'static,
+    ) -> Self {
+        Self::entry(Some(id.into()), label).with_provider(TrayMenuProvider::new(provider))
     }
 
     pub fn label(label: impl Into<String>) -> Self {
@@ -95,17 +326,59 @@ impl TrayMenuItem {
     }
 
     pub fn info(label: impl Into<String>) -> Self {
+        Self::entry(None, label)
+            .enabled(false)
+            .role(TrayMenuItemRole::Info)
+    }
+
+    // The base of the entry constructors: an enabled, visible entry with nothing attached.
+    fn entry(id: Option<String>, label: impl Into<String>) -> Self {
         Self::Submenu {
-            id: None,
+            id,
             label: label.into(),
-            enabled: false,
+            enabled: true,
             visible: true,
-            role: TrayMenuItemRole::Info,
+            role: TrayMenuItemRole::Standard,
             toggle_type: None,
+            icon: None,
+            shortcut: Vec::new(),
+            mnemonic: None,
+            payload: None,
+            on_click: None,
+            action: None,
+            provider: None,
             children: Vec::new(),
         }
     }
 
+    fn with_toggle(mut self, toggle: TrayToggleType) -> Self {
+        if let Self::Submenu { toggle_type, .. } = &mut self {
+            *toggle_type = Some(toggle);
+        }
+        self
+    }
+
+    fn with_action(mut self, menu_action: TrayMenuAction) -> Self {
+        if let Self::Submenu { action, .. } = &mut self {
+            *action = Some(menu_action);
+        }
+        self
+    }
+
+    fn with_provider(mut self, menu_provider: TrayMenuProvider) -> Self {
+        if let Self::Submenu { provider, .. } = &mut self {
+            *provider = Some(menu_provider);
+        }
+        self
+    }
+
+    fn with_children(mut self, items: Vec<TrayMenuItem>) -> Self {
+        if let Self::Submenu { children, .. } = &mut self {
+            *children = items;
+        }
+        self
+    }
+
     pub fn enabled(mut self, enabled: bool) -> Self {
         if let Self::Submenu {
             enabled: item_enabled,
@@ -117,6 +390,110 @@ impl TrayMenuItem {
         self
     }
 
+    /// Sets the entry's role. Hosts that support dbusmenu dispositions style informative,
+    /// warning and alert entries distinctly; Windows and macOS show them as regular entries.
+    pub fn role(mut self, role: TrayMenuItemRole) -> Self {
+        if let Self::Submenu {
+            role: item_role, ..
+        } = &mut self
+        {
+            *item_role = role;
+        }
+        self
+    }
+
+    /// Shows `icon` next to the entry.
+    pub fn icon(mut self, icon: impl Into<Image>) -> Self {
+        if let Self::Submenu {
+            icon: item_icon, ..
+        } = &mut self
+        {
+            *item_icon = Some(TrayMenuIcon::Image(Arc::new(icon.into())));
+        }
+        self
+    }
+
+    /// Shows the themed icon `name` next to the entry. Only honored on Linux.
+    pub fn icon_name(mut self, name: impl Into<String>) -> Self {
+        if let Self::Submenu {
+            icon: item_icon, ..
+        } = &mut self
+        {
+            *item_icon = Some(TrayMenuIcon::Named(name.into()));
+        }
+        self
+    }
+
+    /// Shows a keyboard shortcut hint next to the entry, e.g. `ctrl-shift-p`.
+    ///
+    /// Multiple keystrokes form a chord sequence. The hint is informational; the tray does not
+    /// bind the keys.
+    pub fn shortcut(mut self, keystrokes: impl IntoIterator<Item = Keystroke>) -> Self {
+        if let Self::Submenu {
+            shortcut: item_shortcut,
+            ..
+        } = &mut self
+        {
+            *item_shortcut = keystrokes.into_iter().collect();
+        }
+        self
+    }
+
+    /// Underlines the first occurrence of `mnemonic` in the label (case-insensitive) so it can
+    /// be activated from the keyboard while the menu is open.
+    ///
+    /// Labels are otherwise shown verbatim; characters hosts treat as mnemonic markers (such
+    /// as `_`) are escaped automatically.
+    pub fn mnemonic(mut self, mnemonic: char) -> Self {
+        if let Self::Submenu {
+            mnemonic: item_mnemonic,
+            ..
+        } = &mut self
+        {
+            *item_mnemonic = Some(mnemonic);
+        }
+        self
+    }
+
+    /// Attaches `payload` to the entry; it is cloned into the [`TrayEvent::MenuClick`] emitted
+    /// when the entry is clicked.
+    pub fn payload(mut self, payload: impl Any + Send + Sync) -> Self {
+        if let Self::Submenu {
+            payload: item_payload,
+            ..
+        } = &mut self
+        {
+            *item_payload = Some(TrayMenuPayload::new(payload));
+        }
+        self
+    }
+
+    /// Runs `on_click` when the entry is clicked. Entries with a click handler don't emit
+    /// [`TrayEvent::MenuClick`].
+    pub fn on_click(mut self, on_click: impl Fn(&mut App) + Send + Sync + 'static) -> Self {
+        if let Self::Submenu {
+            on_click: item_on_click,
+            ..
+        } = &mut self
+        {
+            *item_on_click = Some(TrayMenuCallback::new(on_click));
+        }
+        self
+    }
+
+    /// Disables an entry created with [`TrayMenuItem::dispatch`] while no handler for its action
+    /// is available. Availability is re-evaluated on every flush.
+    pub fn auto_disable(mut self, auto_disable: bool) -> Self {
+        if let Self::Submenu {
+            action: Some(action),
+            ..
+        } = &mut self
+        {
+            action.auto_disable = auto_disable;
+        }
+        self
+    }
+
     pub fn visible(mut self, visible: bool) -> Self {
         match &mut self {
             Self::Separator {
@@ -131,26 +508,182 @@ impl TrayMenuItem {
         self
     }
 
-    pub(crate) fn menu_event_id(&self) -> Option<&str> {
+    /// The header row every backend renders below a labeled separator.
+    pub(crate) fn section_header(&self) -> Option<TrayMenuItem> {
+        match self {
+            Self::Separator {
+                label: Some(label),
+                visible,
+            } => Some(Self::info(label.clone()).visible(*visible)),
+            _ => None,
+        }
+    }
+
+    /// The click target for this entry, if it is clickable. `parents` holds the IDs of the
+    /// submenus containing it, outermost first.
+    pub(crate) fn menu_target(&self, parents: &[String]) -> Option<TrayMenuTarget> {
         match self {
             Self::Separator { .. } => None,
             Self::Submenu {
                 id,
+                label,
                 enabled,
                 role,
+                toggle_type,
+                payload,
+                on_click,
+                action,
+                provider,
                 children,
                 ..
-            } if *enabled && *role == TrayMenuItemRole::Standard && children.is_empty() => {
-                id.as_deref()
+            } if *enabled
+                && *role != TrayMenuItemRole::Info
+                && children.is_empty()
+                && provider.is_none()
+                && (id.is_some()
+                    || payload.is_some()
+                    || on_click.is_some()
+                    || action.is_some()) =>
+            {
+                Some(TrayMenuTarget {
+                    id: id.clone().unwrap_or_default(),
+                    label: label.clone(),
+                    toggle_type: toggle_type.clone(),
+                    parents: parents.to_vec(),
+                    payload: payload.clone(),
+                    on_click: on_click.clone(),
+                    action: action.clone(),
+                    checked: None,
+                    timestamp: None,
+                })
             }
             Self::Submenu { .. } => None,
         }
     }
 }
 
+impl TrayMenuItem {
+    /// Converts gpui application menu entries, e.g. those passed to `cx.set_menus`, into tray
+    /// menu entries. Actions are cloned and dispatched through [`App::dispatch_action`]; system
+    /// menus such as "Services" have no tray counterpart and are skipped.
+    pub fn from_menu_items(items: &[gpui::MenuItem]) -> Vec<Self> {
+        items.iter().filter_map(Self::from_menu_item).collect()
+    }
+
+    fn from_menu_item(item: &gpui::MenuItem) -> Option<Self> {
+        match item {
+            gpui::MenuItem::Separator => Some(Self::separator()),
+            gpui::MenuItem::Submenu(menu) => Some(menu.into()),
+            gpui::MenuItem::SystemMenu(_) => None,
+            gpui::MenuItem::Action {
+                name,
+                action,
+                checked,
+                ..
+            } => {
+                let item = Self::entry(None, name.to_string())
+                    .with_action(TrayMenuAction::new(action.boxed_clone()));
+                Some(if *checked {
+                    item.with_toggle(TrayToggleType::Checkbox(true))
+                } else {
+                    item
+                })
+            }
+        }
+    }
+}
+
+impl From<&gpui::Menu> for TrayMenuItem {
+    fn from(menu: &gpui::Menu) -> Self {
+        let name = menu.name.to_string();
+        Self::menu(name.clone(), name, Self::from_menu_items(&menu.items))
+    }
+}
+
+impl From<gpui::Menu> for TrayMenuItem {
+    fn from(menu: gpui::Menu) -> Self {
+        (&menu).into()
+    }
+}
+
+/// What a clickable menu entry resolves to once the native menu reports a click.
+#[derive(Clone, Debug)]
+pub(crate) struct TrayMenuTarget {
+    pub(crate) id: String,
+    pub(crate) label: String,
+    pub(crate) toggle_type: Option<TrayToggleType>,
+    pub(crate) parents: Vec<String>,
+    pub(crate) payload: Option<TrayMenuPayload>,
+    pub(crate) on_click: Option<TrayMenuCallback>,
+    pub(crate) action: Option<TrayMenuAction>,
+    /// Toggle state after the click, when the runtime flipped it (see [`TrayState::auto_toggle`]).
+    pub(crate) checked: Option<bool>,
+    /// Host-provided time of the click, filled in by the backend that received it.
+    pub(crate) timestamp: Option<u32>,
+}
+
+impl TrayMenuTarget {
+    /// Runs the entry's click handler or action, falling back to [`TrayEvent::MenuClick`].
+    pub(crate) fn activate(self, callback: &TrayEventCallbackSlot, cx: &mut App) {
+        if let Some(on_click) = &self.on_click {
+            (on_click.0)(cx);
+        } else if let Some(action) = &self.action {
+            cx.dispatch_action(action.action.as_ref());
+        } else {
+            emit_event(callback, self.into_event(), cx);
+        }
+    }
+
+    pub(crate) fn into_event(self) -> TrayEvent {
+        TrayEvent::MenuClick {
+            id: self.id,
+            payload: self.payload,
+            checked: self.checked,
+            label: self.label,
+            toggle_type: self.toggle_type,
+            parents: self.parents,
+            timestamp: self.timestamp,
+        }
+    }
+}
+
+pub(crate) fn emit_event(callback: &TrayEventCallbackSlot, event: TrayEvent, cx: &mut App) {
+    if let Ok(mut slot) = callback.lock()
+        && let Some(cb) = slot.as_mut()
+    {
+        cb(event, cx);
+    }
+}
+
+/// Render `label` for a native menu that uses `marker` to prefix mnemonics.
+///
+/// Literal `marker` characters are doubled, and `marker` is inserted before the first character
+/// matching `mnemonic`.
+#[cfg_attr(target_os = "macos", allow(dead_code))]
+pub(crate) fn mnemonic_label(label: &str, mnemonic: Option<char>, marker: char) -> String {
+    let mut mnemonic = mnemonic.map(|c| c.to_lowercase().collect::<String>());
+    let mut rendered = String::with_capacity(label.len() + 1);
+    for c in label.chars() {
+        if c == marker {
+            rendered.push(marker);
+        } else if mnemonic
+            .as_deref()
+            .is_some_and(|m| c.to_lowercase().eq(m.chars()))
+        {
+            rendered.push(marker);
+            mnemonic = None;
+        }
+        rendered.push(c);
+    }
+    rendered
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum TrayClickAction {
     EmitEvent,
+    /// Opens the tray menu. On Linux the host draws the menu, so this is a request: a `left`
+    /// policy of `OpenMenu` sets the StatusNotifierItem `ItemIsMenu` property, and hosts that
+    /// ignore it keep their own left-click behavior. Right clicks open the menu on every host.
     OpenMenu,
     Ignore,
 }
@@ -161,11 +694,23 @@ pub enum TrayClickKind {
     Double,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct TrayClickPolicy {
     pub left: TrayClickAction,
     pub right: TrayClickAction,
+    /// Middle click (`SecondaryActivate` in the StatusNotifierItem spec).
+    pub middle: TrayClickAction,
     pub double_click: TrayClickAction,
+    /// ID of a menu entry to activate on middle click instead of applying `middle`, like
+    /// ayatana's `XAyatanaSecondaryActivateTarget`. `middle` still applies while no enabled
+    /// entry has this ID.
+    pub middle_menu_item: Option<String>,
+    /// Longest gap between two left clicks that still counts as a double click where the
+    /// host only reports single clicks (Linux).
+    pub double_click_interval: Duration,
+    /// Holds a single left click back until `double_click_interval` has passed without a
+    /// second click, so a double click isn't also reported as a single click. Linux only.
+    pub delay_single_click: bool,
 }
 
 impl TrayClickPolicy {
@@ -183,12 +728,40 @@ impl TrayClickPolicy {
         self
     }
 
+    pub fn middle(mut self, action: TrayClickAction) -> Self {
+        self.middle = action;
+        self
+    }
+
     pub fn double_click(mut self, action: TrayClickAction) -> Self {
         self.double_click = action;
         self
     }
+
+    pub fn double_click_interval(mut self, interval: Duration) -> Self {
+        self.double_click_interval = interval;
+        self
+    }
+
+    pub fn delay_single_click(mut self, delay: bool) -> Self {
+        self.delay_single_click = delay;
+        self
+    }
+
+    /// Activates the menu entry with the given ID on middle click, as if it had been clicked
+    /// in the menu.
+    pub fn middle_menu_item(mut self, id: impl Into<String>) -> Self {
+        self.middle_menu_item = Some(id.into());
+        self
+    }
 }
 
+/// How often the software attention blink switches icons, see [`TrayState::attention_blink`].
+pub(crate) const ATTENTION_BLINK_INTERVAL: Duration = Duration::from_millis(500);
+
+// GTK's default double-click time.
+const DEFAULT_DOUBLE_CLICK_INTERVAL: Duration = Duration::from_millis(400);
+
 impl Default for TrayClickPolicy {
     fn default() -> Self {
         #[cfg(target_os = "macos")]
@@ -196,7 +769,11 @@ impl Default for TrayClickPolicy {
             Self {
                 left: TrayClickAction::OpenMenu,
                 right: TrayClickAction::OpenMenu,
+                middle: TrayClickAction::OpenMenu,
                 double_click: TrayClickAction::OpenMenu,
+                middle_menu_item: None,
+                double_click_interval: DEFAULT_DOUBLE_CLICK_INTERVAL,
+                delay_single_click: false,
             }
         }
 
@@ -205,7 +782,11 @@ impl Default for TrayClickPolicy {
             Self {
                 left: TrayClickAction::EmitEvent,
                 right: TrayClickAction::OpenMenu,
+                middle: TrayClickAction::EmitEvent,
                 double_click: TrayClickAction::EmitEvent,
+                middle_menu_item: None,
+                double_click_interval: DEFAULT_DOUBLE_CLICK_INTERVAL,
+                delay_single_click: false,
             }
         }
     }
@@ -223,18 +804,66 @@ pub enum TrayEvent {
     },
     MenuClick {
         id: String,
+        payload: Option<TrayMenuPayload>,
+        /// The entry's new toggle state, if the runtime flipped it locally.
+        checked: Option<bool>,
+        /// The entry's label as shown in the menu, without mnemonic markers.
+        label: String,
+        /// The entry's toggle state when it was clicked, before any local flip.
+        toggle_type: Option<TrayToggleType>,
+        /// IDs of the submenus containing the entry, outermost first. Submenus without an ID
+        /// contribute an empty string.
+        parents: Vec<String>,
+        /// When the host reported the click, in milliseconds. The epoch is host specific (the
+        /// dbusmenu event timestamp on Linux, `GetMessageTime` on Windows, time since boot on
+        /// macOS), so only compare timestamps from the same tray. The value wraps around after
+        /// about 49.7 days, so compare with `wrapping_sub`.
+        timestamp: Option<u32>,
+    },
+    /// The context menu, or the submenu with the given ID, was opened. `id` is `None` for the
+    /// root menu and for submenus without an ID. Windows and macOS only report the root menu.
+    MenuOpened {
+        id: Option<String>,
+    },
+    /// Counterpart of [`TrayEvent::MenuOpened`].
+    MenuClosed {
+        id: Option<String>,
+    },
+    /// The pointer or keyboard focus moved onto the menu entry with the given ID. Windows and
+    /// macOS only report clickable entries.
+    MenuItemHovered {
+        id: String,
     },
 }
 
+impl TrayEvent {
+    /// The payload of a [`TrayEvent::MenuClick`], if it holds a `T`.
+    pub fn payload<T: Any>(&self) -> Option<&T> {
+        match self {
+            Self::MenuClick {
+                payload: Some(payload),
+                ..
+            } => payload.downcast_ref(),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct TrayState {
     pub(crate) visible: bool,
-    pub(crate) icon: Option<Rc<Image>>,
+    pub(crate) icon: Option<Arc<Image>>,
     pub(crate) title: String,
     pub(crate) tooltip: String,
     pub(crate) description: String,
     pub(crate) submenus: Vec<TrayMenuItem>,
     pub(crate) click_policy: TrayClickPolicy,
+    pub(crate) auto_toggle: bool,
+    pub(crate) attention_icon: Option<Arc<Image>>,
+    pub(crate) attention_movie: Option<String>,
+    pub(crate) attention_blink: bool,
+    pub(crate) overlay_icon: Option<Arc<Image>>,
+    pub(crate) overlay_icon_name: Option<String>,
 }
 
 impl TrayState {
@@ -247,6 +876,12 @@ impl TrayState {
             description: String::new(),
             submenus: Vec::new(),
             click_policy: TrayClickPolicy::default(),
+            auto_toggle: false,
+            attention_icon: None,
+            attention_movie: None,
+            attention_blink: false,
+            overlay_icon: None,
+            overlay_icon_name: None,
         }
     }
 
@@ -256,7 +891,7 @@ impl TrayState {
     }
 
     pub fn icon(mut self, icon: impl Into<Image>) -> Self {
-        self.icon = Some(Rc::new(icon.into()));
+        self.icon = Some(Arc::new(icon.into()));
         self
     }
 
@@ -280,10 +915,380 @@ impl TrayState {
         self
     }
 
+    pub fn submenus(mut self, submenus: impl IntoIterator<Item = TrayMenuItem>) -> Self {
+        self.submenus.extend(submenus);
+        self
+    }
+
     pub fn click_policy(mut self, click_policy: TrayClickPolicy) -> Self {
         self.click_policy = click_policy;
         self
     }
+
+    /// Flips checkbox and radio entries as soon as they are clicked, without waiting for the
+    /// app to set a new state. The new value is reported in [`TrayEvent::MenuClick`] and kept
+    /// in the tray's desired state.
+    pub fn auto_toggle(mut self, auto_toggle: bool) -> Self {
+        self.auto_toggle = auto_toggle;
+        self
+    }
+
+    /// Asks for the user's attention, e.g. for an unread message: StatusNotifierItem hosts get
+    /// `Status = NeedsAttention` and show `icon` as the attention icon. Set a state without
+    /// attention to clear it.
+    pub fn attention(mut self, icon: impl Into<Image>) -> Self {
+        self.attention_icon = Some(Arc::new(icon.into()));
+        self
+    }
+
+    /// Names a themed animation that StatusNotifierItem hosts may play while the item needs
+    /// attention (`AttentionMovieName`). Also puts the item into the attention state.
+    pub fn attention_movie(mut self, name: impl Into<String>) -> Self {
+        self.attention_movie = Some(name.into());
+        self
+    }
+
+    /// Alternates between the regular and the attention icon while the item needs attention,
+    /// for hosts that ignore the attention state (including Windows and macOS, which have
+    /// none). Blinking stops when the attention is cleared or the item is clicked.
+    pub fn attention_blink(mut self, blink: bool) -> Self {
+        self.attention_blink = blink;
+        self
+    }
+
+    /// Shows `icon` as a badge on top of the main icon, e.g. an "offline" or "syncing" marker,
+    /// without re-rendering the main icon. StatusNotifierItem hosts only (`OverlayIconPixmap`).
+    pub fn overlay_icon(mut self, icon: impl Into<Image>) -> Self {
+        self.overlay_icon = Some(Arc::new(icon.into()));
+        self
+    }
+
+    /// Like [`TrayState::overlay_icon`], with a freedesktop icon theme name
+    /// (`OverlayIconName`). Hosts prefer the name when it resolves.
+    pub fn overlay_icon_name(mut self, name: impl Into<String>) -> Self {
+        self.overlay_icon_name = Some(name.into());
+        self
+    }
+
+    pub(crate) fn needs_attention(&self) -> bool {
+        self.attention_icon.is_some() || self.attention_movie.is_some()
+    }
+
+    fn blinks(&self) -> bool {
+        self.attention_blink && self.attention_icon.is_some()
+    }
+}
+
+fn icon_changed(a: &Option<Arc<Image>>, b: &Option<Arc<Image>>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => !Arc::ptr_eq(a, b) && a != b,
+        (None, None) => false,
+        _ => true,
+    }
+}
+
+/// Whether two menus differ in anything the platform shows. Payloads, callbacks and providers
+/// only count by their presence, so rebuilding a menu with fresh closures doesn't re-render it.
+fn menu_changed(a: &[TrayMenuItem], b: &[TrayMenuItem]) -> bool {
+    a.len() != b.len()
+        || a.iter().zip(b).any(|pair| match pair {
+            (
+                TrayMenuItem::Separator { label, visible },
+                TrayMenuItem::Separator {
+                    label: other_label,
+                    visible: other_visible,
+                },
+            ) => label != other_label || visible != other_visible,
+            (
+                TrayMenuItem::Submenu {
+                    id,
+                    label,
+                    enabled,
+                    visible,
+                    role,
+                    toggle_type,
+                    icon,
+                    shortcut,
+                    mnemonic,
+                    payload,
+                    on_click,
+                    action,
+                    provider,
+                    children,
+                },
+                TrayMenuItem::Submenu {
+                    id: other_id,
+                    label: other_label,
+                    enabled: other_enabled,
+                    visible: other_visible,
+                    role: other_role,
+                    toggle_type: other_toggle_type,
+                    icon: other_icon,
+                    shortcut: other_shortcut,
+                    mnemonic: other_mnemonic,
+                    payload: other_payload,
+                    on_click: other_on_click,
+                    action: other_action,
+                    provider: other_provider,
+                    children: other_children,
+                },
+            ) => {
+                id != other_id
+                    || label != other_label
+                    || enabled != other_enabled
+                    || visible != other_visible
+                    || role != other_role
+                    || toggle_type != other_toggle_type
+                    || icon != other_icon
+                    || shortcut != other_shortcut
+                    || mnemonic != other_mnemonic
+                    || payload.is_some() != other_payload.is_some()
+                    || on_click.is_some() != other_on_click.is_some()
+                    || action != other_action
+                    || provider.is_some() != other_provider.is_some()
+                    || menu_changed(children, other_children)
+            }
+            _ => true,
+        })
+}
+
+/// Finds the entry of `items` that `is_source` picks out and returns the entry at the same
+/// position in `other`, which must have the same structure.
+fn counterpart<'a>(
+    items: &[TrayMenuItem],
+    other: &'a [TrayMenuItem],
+    is_source: &impl Fn(&TrayMenuItem) -> bool,
+) -> Option<&'a TrayMenuItem> {
+    items.iter().zip(other).find_map(|(item, other_item)| {
+        if is_source(item) {
+            return Some(other_item);
+        }
+        match (item, other_item) {
+            (
+                TrayMenuItem::Submenu { children, .. },
+                TrayMenuItem::Submenu {
+                    children: other_children,
+                    ..
+                },
+            ) => counterpart(children, other_children, is_source),
+            _ => None,
+        }
+    })
+}
+
+impl TrayState {
+    /// Reports which fields differ between `self` and `other`. Menu entries are compared by
+    /// what they show: payloads, callbacks and providers only count by their presence.
+    pub fn diff(&self, other: &TrayState) -> TrayStateDiff {
+        TrayStateDiff {
+            visible: self.visible != other.visible,
+            icon: icon_changed(&self.icon, &other.icon),
+            title: self.title != other.title,
+            tooltip: self.tooltip != other.tooltip,
+            description: self.description != other.description,
+            menu: menu_changed(&self.submenus, &other.submenus),
+            click_policy: self.click_policy != other.click_policy,
+            attention: icon_changed(&self.attention_icon, &other.attention_icon)
+                || self.attention_movie != other.attention_movie
+                || self.attention_blink != other.attention_blink,
+            overlay: icon_changed(&self.overlay_icon, &other.overlay_icon)
+                || self.overlay_icon_name != other.overlay_icon_name,
+            blink: false,
+        }
+    }
+
+    /// Toggles the entry with the given ID under the submenus named by `parents` (see
+    /// [`TrayMenuTarget::parents`]) like a click would and returns its new state.
+    fn toggle_menu_item(&mut self, parents: &[String], id: &str) -> Option<bool> {
+        fn find<'a>(
+            items: &'a mut [TrayMenuItem],
+            parents: &[String],
+            id: &str,
+        ) -> Option<&'a mut Option<TrayToggleType>> {
+            match parents.split_first() {
+                Some((parent, parents)) => items.iter_mut().find_map(|item| match item {
+                    TrayMenuItem::Submenu {
+                        id: item_id,
+                        children,
+                        ..
+                    } if item_id.as_deref().unwrap_or_default() == parent => {
+                        find(children, parents, id)
+                    }
+                    _ => None,
+                }),
+                None => items.iter_mut().find_map(|item| match item {
+                    TrayMenuItem::Submenu {
+                        id: Some(item_id),
+                        toggle_type: toggle_type @ Some(_),
+                        ..
+                    } if item_id == id => Some(toggle_type),
+                    _ => None,
+                }),
+            }
+        }
+
+        fn uncheck_group(items: &mut [TrayMenuItem], group: &str) {
+            for item in items {
+                if let TrayMenuItem::Submenu {
+                    toggle_type,
+                    children,
+                    ..
+                } = item
+                {
+                    if let Some(TrayToggleType::GroupedRadio {
+                        group: item_group,
+                        checked,
+                    }) = toggle_type
+                        && item_group == group
+                    {
+                        *checked = false;
+                    }
+                    uncheck_group(children, group);
+                }
+            }
+        }
+
+        // Clear the entry's radio group first so that only the clicked entry ends up checked,
+        // even if another entry in the group shares its ID.
+        if let Some(TrayToggleType::GroupedRadio { group, .. }) =
+            find(&mut self.submenus, parents, id)?.clone()
+        {
+            uncheck_group(&mut self.submenus, &group);
+        }
+
+        let toggle_type = find(&mut self.submenus, parents, id)?;
+        match toggle_type {
+            Some(TrayToggleType::Checkbox(checked)) => *checked = !*checked,
+            Some(TrayToggleType::Indeterminate) => {
+                *toggle_type = Some(TrayToggleType::Checkbox(true));
+            }
+            Some(TrayToggleType::Radio(checked)) => *checked = true,
+            Some(TrayToggleType::GroupedRadio { checked, .. }) => *checked = true,
+            None => return None,
+        }
+        toggle_type.as_ref().map(TrayToggleType::is_checked)
+    }
+
+    /// Leaves one checked entry in every radio group: the first one that wasn't checked in
+    /// `previous`, so that checking a new entry unchecks the old selection, or the first
+    /// checked one when nothing changed or there is no previous state.
+    pub(crate) fn enforce_radio_groups(&mut self, previous: Option<&TrayState>) {
+        fn checked_entries(items: &[TrayMenuItem], checked: &mut Vec<(String, String)>) {
+            for item in items {
+                let TrayMenuItem::Submenu {
+                    id,
+                    toggle_type,
+                    children,
+                    ..
+                } = item
+                else {
+                    continue;
+                };
+                if let Some(TrayToggleType::GroupedRadio {
+                    group,
+                    checked: true,
+                }) = toggle_type
+                {
+                    checked.push((group.clone(), id.clone().unwrap_or_default()));
+                }
+                checked_entries(children, checked);
+            }
+        }
+
+        fn enforce(
+            items: &mut [TrayMenuItem],
+            winners: &HashMap<String, usize>,
+            positions: &mut HashMap<String, usize>,
+        ) {
+            for item in items {
+                let TrayMenuItem::Submenu {
+                    toggle_type,
+                    children,
+                    ..
+                } = item
+                else {
+                    continue;
+                };
+                if let Some(TrayToggleType::GroupedRadio {
+                    group,
+                    checked: checked @ true,
+                }) = toggle_type
+                {
+                    let position = positions.entry(group.clone()).or_default();
+                    *checked = *position == winners.get(group).copied().unwrap_or_default();
+                    *position += 1;
+                }
+                enforce(children, winners, positions);
+            }
+        }
+
+        let mut checked = Vec::new();
+        checked_entries(&self.submenus, &mut checked);
+        let mut previously_checked = Vec::new();
+        if let Some(previous) = previous {
+            checked_entries(&previous.submenus, &mut previously_checked);
+        }
+
+        // Position of the winner among each group's checked entries; groups without a newly
+        // checked entry keep their first one.
+        let mut winners = HashMap::new();
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for entry in &checked {
+            let index = counts.entry(entry.0.as_str()).or_default();
+            if previous.is_some() && !previously_checked.contains(entry) {
+                winners.entry(entry.0.clone()).or_insert(*index);
+            }
+            *index += 1;
+        }
+
+        enforce(&mut self.submenus, &winners, &mut HashMap::new());
+    }
+
+    /// Disables auto-disabling action entries that currently have no handler.
+    pub(crate) fn resolve_action_availability(&mut self, cx: &mut App) {
+        resolve_action_availability(&mut self.submenus, cx);
+    }
+
+    /// Replaces the children of lazy submenus with their provider's output, including lazy
+    /// submenus nested in that output, for backends that cannot populate menus on demand.
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub(crate) fn populate_lazy_submenus(&mut self, cx: &mut App) {
+        populate_lazy_submenus(&mut self.submenus, cx);
+    }
+}
+
+fn resolve_action_availability(items: &mut [TrayMenuItem], cx: &mut App) {
+    for item in items {
+        if let TrayMenuItem::Submenu {
+            enabled,
+            action,
+            children,
+            ..
+        } = item
+        {
+            if let Some(action) = action
+                && action.auto_disable
+                && !cx.is_action_available(action.action.as_ref())
+            {
+                *enabled = false;
+            }
+            resolve_action_availability(children, cx);
+        }
+    }
+}
+
+fn populate_lazy_submenus(items: &mut [TrayMenuItem], cx: &mut App) {
+    for item in items {
+        if let TrayMenuItem::Submenu {
+            provider, children, ..
+        } = item
+        {
+            if let Some(provider) = provider {
+                *children = provider.populate(cx);
+            }
+            populate_lazy_submenus(children, cx);
+        }
+    }
 }
 
 impl Default for TrayState {
@@ -292,10 +1297,53 @@ impl Default for TrayState {
     }
 }
 
+/// Fields that changed between two [`TrayState`]s, see [`TrayState::diff`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TrayStateDiff {
+    pub visible: bool,
+    pub icon: bool,
+    pub title: bool,
+    pub tooltip: bool,
+    pub description: bool,
+    pub menu: bool,
+    pub click_policy: bool,
+    /// The attention icon, animation or blink setting.
+    pub attention: bool,
+    /// The overlay icon or its name.
+    pub overlay: bool,
+    // The blink phase, i.e. whether the attention icon stands in for the icon. It isn't part
+    // of a `TrayState`, so only the runtime's flush diff sets it.
+    pub(crate) blink: bool,
+}
+
+impl TrayStateDiff {
+    pub(crate) fn all() -> Self {
+        Self {
+            visible: true,
+            icon: true,
+            title: true,
+            tooltip: true,
+            description: true,
+            menu: true,
+            click_policy: true,
+            attention: true,
+            overlay: true,
+            blink: true,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
 #[derive(Clone)]
 pub(crate) struct VersionedTrayState {
     pub(crate) version: u64,
     pub(crate) state: TrayState,
+    /// Whether the software blink shows the attention icon in place of the icon. Backends keep
+    /// both icons decoded and swap them, rather than re-decoding on every phase.
+    pub(crate) blink_on: bool,
 }
 
 #[derive(Clone)]
@@ -305,6 +1353,10 @@ pub(crate) struct TrayRuntimeState {
     pub(crate) flush_scheduled: bool,
     pub(crate) flushing: bool,
     next_version: u64,
+    // Software attention blink: whether the attention icon currently replaces the icon, and
+    // whether a click ended the blink for the current attention request.
+    blink_on: bool,
+    blink_stopped: bool,
 }
 
 impl TrayRuntimeState {
@@ -315,18 +1367,160 @@ impl TrayRuntimeState {
             flush_scheduled: false,
             flushing: false,
             next_version: 1,
+            blink_on: false,
+            blink_stopped: false,
         };
         let _ = runtime.set_desired_state(initial);
         runtime
     }
 
-    pub(crate) fn set_desired_state(&mut self, state: TrayState) -> bool {
+    pub(crate) fn set_desired_state(&mut self, mut state: TrayState) -> bool {
+        state.enforce_radio_groups(self.desired_state.as_ref().map(|desired| &desired.state));
+
+        // A new attention request blinks again even if the last one was clicked away.
+        let attention_changed = self
+            .desired_state
+            .as_ref()
+            .is_none_or(|desired| desired.state.diff(&state).attention);
+        if attention_changed {
+            self.blink_on = false;
+            self.blink_stopped = false;
+        }
+
+        // Nothing to do if the state matches what the platform already shows.
+        let unchanged = !self.flushing && self.applied_diff(&state).is_empty();
+
         let version = self.next_version;
         self.next_version = self.next_version.saturating_add(1);
-        self.desired_state = Some(VersionedTrayState { version, state });
+        self.desired_state = Some(VersionedTrayState {
+            version,
+            state,
+            blink_on: false,
+        });
+        if unchanged {
+            return false;
+        }
         self.request_flush()
     }
 
+    /// Flips the toggle of a clicked entry in the desired state if it opted into
+    /// [`TrayState::auto_toggle`], returning the new value. Schedules a flush when it changed.
+    pub(crate) fn toggle_menu_item(&mut self, parents: &[String], id: &str) -> Option<bool> {
+        let desired = self.desired_state.as_ref()?;
+        if !desired.state.auto_toggle || id.is_empty() {
+            return None;
+        }
+
+        let mut state = desired.state.clone();
+        let checked = state.toggle_menu_item(parents, id)?;
+        self.set_desired_state(state);
+        Some(checked)
+    }
+
+    /// The entry of the desired state at the position of the applied entry that `is_source`
+    /// picks out. A state rebuilt with fresh closures but the same menu isn't flushed, so the
+    /// platform still holds the closures it was last applied with.
+    fn desired_counterpart(
+        &self,
+        is_source: impl Fn(&TrayMenuItem) -> bool,
+    ) -> Option<&TrayMenuItem> {
+        let (applied, desired) = (self.applied_state.as_ref()?, self.desired_state.as_ref()?);
+        if menu_changed(&applied.state.submenus, &desired.state.submenus) {
+            return None;
+        }
+        counterpart(&applied.state.submenus, &desired.state.submenus, &is_source)
+    }
+
+    /// Swaps the payload and callback of a target built from the applied state for those of
+    /// the same entry in the desired state.
+    pub(crate) fn refresh_target(&self, target: &mut TrayMenuTarget) {
+        // Payloads and callbacks compare by identity, so this finds the entry itself.
+        let counterpart = self.desired_counterpart(|item| match item {
+            TrayMenuItem::Submenu {
+                payload, on_click, ..
+            } => {
+                (payload.is_some() || on_click.is_some())
+                    && *payload == target.payload
+                    && *on_click == target.on_click
+            }
+            TrayMenuItem::Separator { .. } => false,
+        });
+        if let Some(TrayMenuItem::Submenu {
+            payload, on_click, ..
+        }) = counterpart
+        {
+            target.payload = payload.clone();
+            target.on_click = on_click.clone();
+        }
+    }
+
+    /// The provider of the desired state's entry in place of an applied lazy submenu's
+    /// `provider`, see [`Self::refresh_target`].
+    pub(crate) fn refresh_provider(&self, provider: TrayMenuProvider) -> TrayMenuProvider {
+        let counterpart = self.desired_counterpart(|item| {
+            matches!(item, TrayMenuItem::Submenu { provider: Some(item_provider), .. } if *item_provider == provider)
+        });
+        match counterpart {
+            Some(TrayMenuItem::Submenu {
+                provider: Some(provider),
+                ..
+            }) => provider.clone(),
+            _ => provider,
+        }
+    }
+
+    /// Fields of `state` that differ from the last applied state; everything when nothing has
+    /// been applied yet.
+    pub(crate) fn applied_diff(&self, state: &TrayState) -> TrayStateDiff {
+        self.applied_state
+            .as_ref()
+            .map(|applied| applied.state.diff(state))
+            .unwrap_or_else(TrayStateDiff::all)
+    }
+
+    /// Fields of a state taken by [`Self::try_begin_flush`] that differ from the last applied
+    /// state, including the blink phase.
+    pub(crate) fn flush_diff(&self, flushed: &VersionedTrayState) -> TrayStateDiff {
+        let mut diff = self.applied_diff(&flushed.state);
+        diff.blink = self
+            .applied_state
+            .as_ref()
+            .is_none_or(|applied| applied.blink_on != flushed.blink_on);
+        diff
+    }
+
+    /// Whether the desired state asks for a software blink that no click has ended yet.
+    pub(crate) fn is_blinking(&self) -> bool {
+        !self.blink_stopped
+            && self
+                .desired_state
+                .as_ref()
+                .is_some_and(|desired| desired.state.blinks())
+    }
+
+    /// Flips the blink phase and schedules a flush to show it. Returns false once the blink
+    /// has ended, so the backend can stop its timer.
+    pub(crate) fn advance_blink(&mut self) -> bool {
+        if !self.is_blinking() {
+            self.blink_on = false;
+            return false;
+        }
+        self.blink_on = !self.blink_on;
+        self.request_flush();
+        true
+    }
+
+    /// Ends the blink after the item was clicked. Returns true if a flush should be scheduled
+    /// to restore the regular icon.
+    pub(crate) fn stop_blink(&mut self) -> bool {
+        if !self.is_blinking() {
+            return false;
+        }
+        self.blink_stopped = true;
+        let restore = std::mem::take(&mut self.blink_on);
+        restore && self.request_flush()
+    }
+
     pub(crate) fn request_flush(&mut self) -> bool {
         if self.flush_scheduled {
             return false;
@@ -339,7 +1533,8 @@ impl TrayRuntimeState {
         if self.flushing || !self.flush_scheduled {
             return None;
         }
-        let desired = self.desired_state.clone()?;
+        let mut desired = self.desired_state.clone()?;
+        desired.blink_on = self.blink_on && self.is_blinking();
         self.flushing = true;
         self.flush_scheduled = false;
         Some(desired)
@@ -433,7 +1628,9 @@ pub fn set_up_tray(
 
 #[cfg(test)]
 mod tests {
-    use super::{TrayRuntimeState, TrayState};
+    use super::{TrayMenuItem, TrayRuntimeState, TrayState, TrayStateDiff, mnemonic_label};
+
+    gpui::actions!(tray_tests, [Open]);
 
     #[test]
     fn tray_state_clones_builder_data() {
@@ -481,4 +1678,309 @@ mod tests {
         assert!(runtime.has_pending_flush());
         assert!(!runtime.flushing);
     }
+
+    #[test]
+    fn mnemonic_label_escapes_literal_markers() {
+        assert_eq!(mnemonic_label("my_file.txt", None, '_'), "my__file.txt");
+        assert_eq!(mnemonic_label("Tom & Jerry", None, '&'), "Tom && Jerry");
+    }
+
+    #[test]
+    fn mnemonic_label_marks_first_matching_character() {
+        assert_eq!(mnemonic_label("Quit", Some('q'), '_'), "_Quit");
+        assert_eq!(
+            mnemonic_label("Open_Recent", Some('R'), '_'),
+            "Open___Recent"
+        );
+        assert_eq!(mnemonic_label("Quit", Some('x'), '_'), "Quit");
+    }
+
+    #[test]
+    fn menu_target_carries_payload_into_click_event() {
+        #[derive(Debug, PartialEq)]
+        enum Command {
+            Connect(&'static str),
+        }
+
+        let item = TrayMenuItem::typed("Connect", Command::Connect("node-a"));
+        let event = item.menu_target(&[]).expect("clickable item").into_event();
+
+        assert_eq!(
+            event.payload::<Command>(),
+            Some(&Command::Connect("node-a"))
+        );
+        assert_eq!(event.payload::<String>(), None);
+    }
+
+    #[test]
+    fn tray_state_diff_reports_changed_fields() {
+        let state = TrayState::new()
+            .title("a")
+            .submenu(TrayMenuItem::checkbox("wifi", "Wi-Fi", false));
+        assert!(state.diff(&state.clone()).is_empty());
+
+        let changed = state.clone().title("b").submenu(TrayMenuItem::separator());
+        let diff = state.diff(&changed);
+        assert!(diff.title && diff.menu);
+        assert!(!diff.icon && !diff.tooltip && !diff.visible && !diff.overlay);
+
+        let badged = state.clone().overlay_icon_name("network-offline");
+        let diff = state.diff(&badged);
+        assert!(diff.overlay && !diff.icon && !diff.attention);
+    }
+
+    #[test]
+    fn runtime_state_skips_flush_for_state_matching_applied() {
+        let mut runtime = TrayRuntimeState::new(TrayState::new().title("a"));
+        let flushing = runtime.try_begin_flush().expect("pending flush");
+        assert!(!runtime.finish_flush(flushing));
+
+        assert!(!runtime.set_desired_state(TrayState::new().title("a")));
+        assert!(runtime.try_begin_flush().is_none());
+        assert!(runtime.set_desired_state(TrayState::new().title("b")));
+    }
+
+    #[test]
+    fn radio_groups_keep_newly_checked_entry() {
+        fn checked(runtime: &TrayRuntimeState) -> Vec<String> {
+            fn collect(items: &[TrayMenuItem], checked: &mut Vec<String>) {
+                for item in items {
+                    if let TrayMenuItem::Submenu {
+                        id,
+                        toggle_type,
+                        children,
+                        ..
+                    } = item
+                    {
+                        if toggle_type
+                            .as_ref()
+                            .is_some_and(|toggle| toggle.is_checked())
+                        {
+                            checked.extend(id.clone());
+                        }
+                        collect(children, checked);
+                    }
+                }
+            }
+            let mut checked = Vec::new();
+            collect(
+                &runtime.desired_state.as_ref().unwrap().state.submenus,
+                &mut checked,
+            );
+            checked
+        }
+        let state = |list: bool, grid: bool| {
+            TrayState::new()
+                .submenu(TrayMenuItem::grouped_radio("view", "list", "List", list))
+                .submenu(TrayMenuItem::menu(
+                    "more",
+                    "More",
+                    vec![TrayMenuItem::grouped_radio("view", "grid", "Grid", grid)],
+                ))
+                .submenu(TrayMenuItem::grouped_radio("sort", "name", "Name", true))
+        };
+
+        // Without a previous state the first checked entry wins.
+        let mut runtime = TrayRuntimeState::new(state(true, true));
+        assert_eq!(checked(&runtime), vec!["list", "name"]);
+
+        // Checking "grid" without unchecking "list" moves the selection.
+        runtime.set_desired_state(state(true, true));
+        assert_eq!(checked(&runtime), vec!["grid", "name"]);
+
+        runtime.set_desired_state(state(false, true));
+        assert_eq!(checked(&runtime), vec!["grid", "name"]);
+        runtime.set_desired_state(state(true, true));
+        assert_eq!(checked(&runtime), vec!["list", "name"]);
+    }
+
+    #[test]
+    fn auto_toggle_flips_entries_in_desired_state() {
+        let state = TrayState::new()
+            .submenu(TrayMenuItem::checkbox("wifi", "Wi-Fi", false))
+            .submenu(TrayMenuItem::grouped_radio("view", "list", "List", true))
+            .submenu(TrayMenuItem::grouped_radio("view", "grid", "Grid", false));
+
+        let mut runtime = TrayRuntimeState::new(state.clone());
+        assert_eq!(runtime.toggle_menu_item(&[], "wifi"), None);
+
+        let mut runtime = TrayRuntimeState::new(state.auto_toggle(true));
+        let _ = runtime.try_begin_flush();
+        assert_eq!(runtime.toggle_menu_item(&[], "wifi"), Some(true));
+        assert_eq!(runtime.toggle_menu_item(&[], "grid"), Some(true));
+        assert_eq!(runtime.toggle_menu_item(&[], "missing"), None);
+
+        let desired = &runtime.desired_state.as_ref().unwrap().state;
+        let checked: Vec<bool> = desired
+            .submenus
+            .iter()
+            .map(|item| match item {
+                TrayMenuItem::Submenu {
+                    toggle_type: Some(toggle),
+                    ..
+                } => toggle.is_checked(),
+                _ => false,
+            })
+            .collect();
+        assert_eq!(checked, vec![true, false, true]);
+    }
+
+    #[test]
+    fn auto_toggle_resolves_entry_by_submenu_path() {
+        let state = TrayState::new()
+            .auto_toggle(true)
+            .submenu(TrayMenuItem::menu(
+                "wired",
+                "Wired",
+                vec![TrayMenuItem::checkbox("enabled", "Enabled", false)],
+            ))
+            .submenu(TrayMenuItem::menu(
+                "wireless",
+                "Wireless",
+                vec![TrayMenuItem::checkbox("enabled", "Enabled", false)],
+            ));
+        let enabled = |runtime: &TrayRuntimeState| -> Vec<bool> {
+            let desired = &runtime.desired_state.as_ref().unwrap().state;
+            desired
+                .submenus
+                .iter()
+                .map(|item| match item {
+                    TrayMenuItem::Submenu { children, .. } => matches!(
+                        children.as_slice(),
+                        [TrayMenuItem::Submenu {
+                            toggle_type: Some(toggle),
+                            ..
+                        }] if toggle.is_checked()
+                    ),
+                    _ => false,
+                })
+                .collect()
+        };
+
+        let mut runtime = TrayRuntimeState::new(state);
+        let _ = runtime.try_begin_flush();
+        assert_eq!(
+            runtime.toggle_menu_item(&["wireless".into()], "enabled"),
+            Some(true)
+        );
+        assert_eq!(enabled(&runtime), vec![false, true]);
+        assert_eq!(
+            runtime.toggle_menu_item(&["wired".into()], "enabled"),
+            Some(true)
+        );
+        assert_eq!(enabled(&runtime), vec![true, true]);
+        assert_eq!(runtime.toggle_menu_item(&[], "enabled"), None);
+    }
+
+    #[test]
+    fn rebuilt_state_with_same_menu_schedules_nothing() {
+        let state = |port: u32| {
+            TrayState::new()
+                .submenu(TrayMenuItem::action("Open", |_| {}))
+                .submenu(TrayMenuItem::typed("Connect", port))
+                .submenu(TrayMenuItem::lazy("recent", "Recent", |_| Vec::new()))
+        };
+        let mut runtime = TrayRuntimeState::new(state(1));
+        let flushed = runtime.try_begin_flush().expect("flush scheduled");
+        assert!(!runtime.finish_flush(flushed));
+
+        assert!(!runtime.set_desired_state(state(1)));
+        assert!(!runtime.set_desired_state(state(2)));
+        assert!(!runtime.has_pending_flush());
+
+        // Clicks on the entries the platform shows still reach the newest closures.
+        let shown = &runtime.applied_state.as_ref().unwrap().state.submenus[1];
+        let mut target = shown.menu_target(&[]).expect("clickable item");
+        runtime.refresh_target(&mut target);
+        assert_eq!(target.into_event().payload::<u32>(), Some(&2));
+
+        let provider = |state: &TrayState| match &state.submenus[2] {
+            TrayMenuItem::Submenu {
+                provider: Some(provider),
+                ..
+            } => provider.clone(),
+            _ => unreachable!(),
+        };
+        let shown = provider(&runtime.applied_state.as_ref().unwrap().state);
+        let desired = provider(&runtime.desired_state.as_ref().unwrap().state);
+        assert!(shown != desired);
+        assert!(runtime.refresh_provider(shown) == desired);
+    }
+
+    #[test]
+    fn attention_blink_alternates_icons_until_clicked() {
+        let icon = gpui::Image::from_bytes(gpui::ImageFormat::Png, vec![1]);
+        let attention = gpui::Image::from_bytes(gpui::ImageFormat::Png, vec![2]);
+        let state = TrayState::new()
+            .icon(icon.clone())
+            .attention(attention.clone())
+            .attention_blink(true);
+
+        let mut runtime = TrayRuntimeState::new(state.clone());
+        let flush = |runtime: &mut TrayRuntimeState| {
+            let flushed = runtime.try_begin_flush().expect("flush scheduled");
+            let diff = runtime.flush_diff(&flushed);
+            let blink_on = flushed.blink_on;
+            runtime.finish_flush(flushed);
+            (blink_on, diff)
+        };
+        assert!(!flush(&mut runtime).0);
+        assert!(runtime.advance_blink());
+        // Phases only swap the icons; they don't count as a new icon.
+        let (blink_on, diff) = flush(&mut runtime);
+        assert!(blink_on);
+        assert_eq!(
+            diff,
+            TrayStateDiff {
+                blink: true,
+                ..TrayStateDiff::default()
+            }
+        );
+
+        assert!(runtime.stop_blink());
+        assert!(!runtime.is_blinking());
+        assert!(!flush(&mut runtime).0);
+        assert!(!runtime.advance_blink());
+
+        // The same request stays silenced; a new one blinks again.
+        runtime.set_desired_state(state);
+        assert!(!runtime.is_blinking());
+        runtime.set_desired_state(TrayState::new().icon(icon));
+        runtime.set_desired_state(TrayState::new().attention(attention).attention_blink(true));
+        assert!(runtime.is_blinking());
+    }
+
+    #[test]
+    fn gpui_menus_convert_into_tray_menu_trees() {
+        let menu = gpui::Menu {
+            name: "File".into(),
+            items: vec![
+                gpui::MenuItem::action("Open", Open),
+                gpui::MenuItem::separator(),
+                gpui::MenuItem::submenu(gpui::Menu {
+                    name: "Recent".into(),
+                    items: Vec::new(),
+                }),
+            ],
+        };
+
+        let TrayMenuItem::Submenu {
+            label, children, ..
+        } = TrayMenuItem::from(&menu)
+        else {
+            panic!("expected submenu");
+        };
+        assert_eq!(label, "File");
+        assert_eq!(children.len(), 3);
+        assert!(
+            children[0]
+                .menu_target(&[])
+                .is_some_and(|target| target.action.is_some())
+        );
+        assert!(matches!(children[1], TrayMenuItem::Separator { .. }));
+        assert!(matches!(
+            &children[2],
+            TrayMenuItem::Submenu { label, children, .. } if label == "Recent" && children.is_empty()
+        ));
+    }
 }
@@ -1,40 +1,83 @@
 use crate::tray::{
-    TrayClickAction, TrayClickKind, TrayClickPolicy, TrayEvent, TrayEventCallback,
-    TrayEventCallbackSlot, TrayMenuItem, TrayRuntimeState, TrayState, TrayToggleType,
-    VersionedTrayState,
+    ATTENTION_BLINK_INTERVAL, TrayClickAction, TrayClickKind, TrayClickPolicy, TrayEvent,
+    TrayEventCallback, TrayEventCallbackSlot, TrayMenuIcon, TrayMenuItem, TrayMenuProvider,
+    TrayMenuTarget, TrayRuntimeState, TrayState, TrayStateDiff, TrayToggleType, emit_event,
+    mnemonic_label,
 };
 use anyhow::{Context as _, Result};
-use gpui::{AsyncApp, MouseButton, Point};
+use gpui::{AsyncApp, Keystroke, MouseButton, Point, Subscription};
+use std::cell::RefCell;
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex, OnceLock, atomic::AtomicU32, atomic::Ordering};
+use std::sync::{Arc, Mutex, atomic::AtomicU32, atomic::Ordering};
+use std::time::{Duration, Instant};
 
 const STATUS_NOTIFIER_WATCHER_INTERFACE: &str = "org.kde.StatusNotifierWatcher";
 const STATUS_NOTIFIER_WATCHER_PATH: &str = "/StatusNotifierWatcher";
 const STATUS_NOTIFIER_WATCHER_DESTINATION: &str = "org.kde.StatusNotifierWatcher";
 
+// The spec requires this path when an item registers with its bus name. Every tray owns a
+// separate connection, so items never collide on it.
 const STATUS_NOTIFIER_ITEM_PATH: &str = "/StatusNotifierItem";
 const DBUS_MENU_PATH: &str = "/MenuBar";
 
+static NEXT_TRAY_INSTANCE: AtomicU32 = AtomicU32::new(0);
+
+thread_local! {
+    // Quit hooks of the live trays, by instance. gpui subscriptions are bound to the main
+    // thread, so they live here rather than in the `Send` handle.
+    static QUIT_SUBSCRIPTIONS: RefCell<HashMap<u32, Subscription>> =
+        RefCell::new(HashMap::new());
+}
+
+/// Unregisters a tray's quit hook when dropped, i.e. once its event loop is gone.
+struct QuitSubscriptionGuard(u32);
+
+impl Drop for QuitSubscriptionGuard {
+    fn drop(&mut self) {
+        // Taken out first so the subscription drops outside the borrow.
+        let _subscription = QUIT_SUBSCRIPTIONS
+            .try_with(|subscriptions| subscriptions.borrow_mut().remove(&self.0))
+            .ok()
+            .flatten();
+    }
+}
+
 fn dispatch_event(async_app: &AsyncApp, callback: &TrayEventCallbackSlot, event: TrayEvent) {
-    let async_app = async_app.clone();
     let callback = callback.clone();
+    defer_on_app(async_app, move |cx| emit_event(&callback, event, cx));
+}
+
+fn dispatch_menu_target(
+    async_app: &AsyncApp,
+    callback: &TrayEventCallbackSlot,
+    target: TrayMenuTarget,
+) {
+    let callback = callback.clone();
+    defer_on_app(async_app, move |cx| target.activate(&callback, cx));
+}
+
+fn defer_on_app(async_app: &AsyncApp, f: impl FnOnce(&mut gpui::App) + 'static) {
     async_app.update(|cx| {
-        cx.defer(move |cx| {
-            if let Ok(mut slot) = callback.lock()
-                && let Some(cb) = slot.as_mut()
-            {
-                cb(event, cx);
-            }
-        });
+        cx.defer(f);
     });
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 enum LinuxEvent {
     Activate(i32, i32),
     SecondaryActivate(i32, i32),
+    ContextMenu(i32, i32),
     Scroll(i32, String),
-    MenuClick(String),
+    MenuClick(TrayMenuTarget),
+    MenuOpened(Option<String>),
+    MenuClosed(Option<String>),
+    MenuItemHovered(String),
+    // The provider has to run on the main thread; `reply` carries `needUpdate` back to the host.
+    AboutToShow {
+        id: i32,
+        provider: TrayMenuProvider,
+        reply: tokio::sync::oneshot::Sender<bool>,
+    },
 }
 
 #[derive(Default, Debug, Clone, zbus::zvariant::Type, serde::Serialize)]
@@ -91,15 +134,42 @@ struct LinuxTrayItem {
     title: String,
     tooltip: String,
     description: String,
-    icon_pixmaps: Vec<Pixmap>,
-    menu: DBusMenu,
+    // Only rebuilt when the icon or menu changed; decoding and diffing them isn't free.
+    icon_pixmaps: Option<Vec<Pixmap>>,
+    menu: Option<DBusMenu>,
     click_policy: TrayClickPolicy,
+    needs_attention: bool,
+    attention_pixmaps: Option<Vec<Pixmap>>,
+    attention_movie: String,
+    overlay_pixmaps: Option<Vec<Pixmap>>,
+    overlay_icon_name: String,
 }
 
-fn linux_item_from_tray_state(item: TrayState) -> Result<LinuxTrayItem> {
-    let icon_pixmaps = icon_pixmaps_from_item(&item)?.unwrap_or_default();
-    let menu = DBusMenu::from_tray_menu_items(&item.submenus);
+fn linux_item_from_tray_state(
+    item: TrayState,
+    diff: &TrayStateDiff,
+    menu_ids: MenuIdAllocator,
+) -> Result<LinuxTrayItem> {
+    let icon_pixmaps = if diff.icon {
+        Some(icon_pixmaps_from_item(item.icon.as_deref())?)
+    } else {
+        None
+    };
+    let attention_pixmaps = if diff.attention {
+        Some(icon_pixmaps_from_item(item.attention_icon.as_deref())?)
+    } else {
+        None
+    };
+    let overlay_pixmaps = if diff.overlay {
+        Some(icon_pixmaps_from_item(item.overlay_icon.as_deref())?)
+    } else {
+        None
+    };
+    let menu = diff
+        .menu
+        .then(|| DBusMenu::from_tray_menu_items(&item.submenus, menu_ids));
     Ok(LinuxTrayItem {
+        needs_attention: item.needs_attention(),
         visible: item.visible,
         title: item.title,
         tooltip: item.tooltip,
@@ -107,12 +177,16 @@ fn linux_item_from_tray_state(item: TrayState) -> Result<LinuxTrayItem> {
         icon_pixmaps,
         menu,
         click_policy: item.click_policy,
+        attention_pixmaps,
+        attention_movie: item.attention_movie.unwrap_or_default(),
+        overlay_pixmaps,
+        overlay_icon_name: item.overlay_icon_name.unwrap_or_default(),
     })
 }
 
-fn icon_pixmaps_from_item(item: &TrayState) -> Result<Option<Vec<Pixmap>>> {
-    let Some(icon) = item.icon.as_ref() else {
-        return Ok(None);
+fn icon_pixmaps_from_item(icon: Option<&gpui::Image>) -> Result<Vec<Pixmap>> {
+    let Some(icon) = icon else {
+        return Ok(Vec::new());
     };
 
     let (width, height, bgra) = crate::icon::decode_gpui_image_to_bgra32(icon)?;
@@ -125,7 +199,7 @@ fn icon_pixmaps_from_item(item: &TrayState) -> Result<Option<Vec<Pixmap>>> {
         if size > width || size > height {
             continue;
         }
-        let scaled = resize_bgra32_nearest(&bgra, width, height, size, size)?;
+        let scaled = crate::icon::resize_bgra32_nearest(&bgra, width, height, size, size)?;
         // Although the SNI spec says "ARGB32", many hosts interpret this as native-endian
         // 0xAARRGGBB pixels (e.g. Qt/cairo ARGB32). On little-endian systems that is
         // byte-ordered BGRA. GPUI already gives us BGRA8, so pass it through.
@@ -137,50 +211,16 @@ fn icon_pixmaps_from_item(item: &TrayState) -> Result<Option<Vec<Pixmap>>> {
         pixmaps.push(Pixmap::new(width as i32, height as i32, bgra));
     }
 
-    Ok(Some(pixmaps))
+    Ok(pixmaps)
 }
 
-fn resize_bgra32_nearest(
-    src: &[u8],
-    src_w: u32,
-    src_h: u32,
-    dst_w: u32,
-    dst_h: u32,
-) -> Result<Vec<u8>> {
-    anyhow::ensure!(
-        src_w > 0 && src_h > 0 && dst_w > 0 && dst_h > 0,
-        "invalid size"
-    );
-    let src_w = src_w as usize;
-    let src_h = src_h as usize;
-    let dst_w = dst_w as usize;
-    let dst_h = dst_h as usize;
-    anyhow::ensure!(
-        src.len() == src_w * src_h * 4,
-        "expected BGRA32 buffer length {}",
-        src_w * src_h * 4
-    );
-
-    let mut dst = vec![0u8; dst_w * dst_h * 4];
-    for y in 0..dst_h {
-        let sy = y * src_h / dst_h;
-        for x in 0..dst_w {
-            let sx = x * src_w / dst_w;
-            let s = (sy * src_w + sx) * 4;
-            let d = (y * dst_w + x) * 4;
-            dst[d..d + 4].copy_from_slice(&src[s..s + 4]);
-        }
-    }
-    Ok(dst)
-}
-
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 enum MenuToggleType {
     Checkmark,
     Radio,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 enum MenuProperty {
     Type(&'static str),
     Label(String),
@@ -188,6 +228,11 @@ enum MenuProperty {
     Visible(bool),
     ToggleType(MenuToggleType),
     ToggleState(i32),
+    IconName(String),
+    IconData(Vec<u8>),
+    Shortcut(Vec<Vec<String>>),
+    ChildrenDisplay(&'static str),
+    Disposition(&'static str),
 }
 
 impl MenuProperty {
@@ -202,10 +247,85 @@ impl MenuProperty {
                 MenuToggleType::Radio => zbus::zvariant::Value::from("radio"),
             },
             Self::ToggleState(s) => zbus::zvariant::Value::from(*s),
+            Self::IconName(s) => zbus::zvariant::Value::from(s.clone()),
+            Self::IconData(bytes) => zbus::zvariant::Value::from(bytes.clone()),
+            Self::Shortcut(keys) => zbus::zvariant::Value::from(keys.clone()),
+            Self::ChildrenDisplay(display) => zbus::zvariant::Value::from(*display),
+            Self::Disposition(disposition) => zbus::zvariant::Value::from(*disposition),
         }
     }
 }
 
+/// Convert keystrokes into the dbusmenu `shortcut` format: one array of key names per
+/// keystroke, modifiers first (e.g. `[["Control", "Shift", "P"]]`).
+fn dbusmenu_shortcut(keystrokes: &[Keystroke]) -> Vec<Vec<String>> {
+    keystrokes
+        .iter()
+        .map(|keystroke| {
+            let modifiers = &keystroke.modifiers;
+            let mut keys = Vec::new();
+            if modifiers.control {
+                keys.push("Control".to_string());
+            }
+            if modifiers.alt {
+                keys.push("Alt".to_string());
+            }
+            if modifiers.shift {
+                keys.push("Shift".to_string());
+            }
+            if modifiers.platform {
+                keys.push("Super".to_string());
+            }
+            keys.push(dbusmenu_key_name(&keystroke.key));
+            keys
+        })
+        .collect()
+}
+
+// Hosts parse key names as X keysyms, the way GTK/Qt accelerators do, so map gpui's key names
+// onto those.
+fn dbusmenu_key_name(key: &str) -> String {
+    let keysym = match key {
+        "enter" => "Return",
+        "escape" => "Escape",
+        "backspace" => "BackSpace",
+        "delete" => "Delete",
+        "insert" => "Insert",
+        "tab" => "Tab",
+        "space" | " " => "space",
+        "up" => "Up",
+        "down" => "Down",
+        "left" => "Left",
+        "right" => "Right",
+        "home" => "Home",
+        "end" => "End",
+        "pageup" => "Page_Up",
+        "pagedown" => "Page_Down",
+        "-" => "minus",
+        "=" => "equal",
+        "+" => "plus",
+        "," => "comma",
+        "." => "period",
+        "/" => "slash",
+        "\\" => "backslash",
+        ";" => "semicolon",
+        "'" => "apostrophe",
+        "`" => "grave",
+        "[" => "bracketleft",
+        "]" => "bracketright",
+        key if key.chars().count() == 1 => return key.to_uppercase(),
+        // Function keys ("f1" -> "F1") and anything else: capitalize the first letter.
+        key => {
+            let mut chars = key.chars();
+            return chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars).collect())
+                .unwrap_or_default();
+        }
+    };
+    keysym.to_string()
+}
+
 #[derive(Default, Debug, Clone, zbus::zvariant::Type, serde::Serialize)]
 struct DBusMenuLayoutItem {
     id: i32,
@@ -227,7 +347,11 @@ impl From<DBusMenuLayoutItem> for zbus::zvariant::Structure<'_> {
 #[derive(Default, Debug, Clone)]
 struct MenuNode {
     id: i32,
-    user_id: Option<String>,
+    item_id: Option<String>,
+    /// IDs of the submenus containing this node, outermost first.
+    parents: Vec<String>,
+    target: Option<TrayMenuTarget>,
+    provider: Option<TrayMenuProvider>,
     properties: HashMap<&'static str, MenuProperty>,
     children: Vec<i32>,
 }
@@ -235,6 +359,83 @@ struct MenuNode {
 #[derive(Debug, Clone)]
 struct DBusMenu {
     nodes: HashMap<i32, MenuNode>,
+    ids: MenuIdAllocator,
+}
+
+/// Hands out dbusmenu node IDs keyed by an item's position in the menu tree, so an entry keeps
+/// its ID across revisions. Keys of removed entries are pruned, and their IDs are never handed
+/// out again. Entries without an ID are keyed by their position among such siblings, so they
+/// get fresh IDs whenever the entries of their menu change: a click the host sends for an
+/// older layout then finds no node, rather than the entry that moved into its position.
+#[derive(Debug, Clone, Default)]
+struct MenuIdAllocator {
+    // (parent node ID, key of the item among its siblings) -> node ID
+    ids: HashMap<(i32, String), i32>,
+    // parent node ID -> keys of its children, and the generation their positional keys carry
+    layouts: HashMap<i32, (Vec<String>, u32)>,
+    last_id: i32,
+    last_generation: u32,
+}
+
+impl MenuIdAllocator {
+    fn id_for(&mut self, parent_id: i32, key: String) -> i32 {
+        *self.ids.entry((parent_id, key)).or_insert_with(|| {
+            self.last_id += 1;
+            self.last_id
+        })
+    }
+
+    /// The generation for the positional keys of a menu with the given child keys, which
+    /// changes whenever the keys do.
+    fn generation_for(&mut self, parent_id: i32, keys: &[String]) -> u32 {
+        match self.layouts.get(&parent_id) {
+            Some((layout, generation)) if layout == keys => *generation,
+            _ => {
+                self.last_generation += 1;
+                self.layouts
+                    .insert(parent_id, (keys.to_vec(), self.last_generation));
+                self.last_generation
+            }
+        }
+    }
+
+    /// Drops the keys of entries that are no longer in `nodes`. `last_id` is kept, so a pruned ID
+    /// is not reused.
+    fn prune(&mut self, nodes: &HashMap<i32, MenuNode>) {
+        self.ids.retain(|_, id| nodes.contains_key(id));
+        self.layouts
+            .retain(|parent_id, _| nodes.contains_key(parent_id));
+    }
+}
+
+// Items with an ID are keyed by it; others by kind plus their position among siblings of the
+// same kind, so relabeling an entry keeps its node.
+fn menu_item_key(item: &TrayMenuItem, key_counts: &mut HashMap<String, usize>) -> String {
+    let key = match item {
+        TrayMenuItem::Separator { .. } => "separator".to_string(),
+        TrayMenuItem::Submenu { id: Some(id), .. } => format!("id:{id}"),
+        TrayMenuItem::Submenu { id: None, .. } => "item".to_string(),
+    };
+    let count = key_counts.entry(key.clone()).or_default();
+    *count += 1;
+    format!("{key}#{count}")
+}
+
+/// Changes between two menu revisions, in the shape of the dbusmenu update signals.
+#[derive(Debug, Default, PartialEq)]
+struct DBusMenuDiff {
+    updated_properties: Vec<(i32, HashMap<String, zbus::zvariant::Value<'static>>)>,
+    removed_properties: Vec<(i32, Vec<String>)>,
+    // Nodes whose children changed; each needs a LayoutUpdated for its whole subtree.
+    layout_parents: Vec<i32>,
+}
+
+impl DBusMenuDiff {
+    fn is_empty(&self) -> bool {
+        self.updated_properties.is_empty()
+            && self.removed_properties.is_empty()
+            && self.layout_parents.is_empty()
+    }
 }
 
 impl DBusMenu {
@@ -247,22 +448,80 @@ impl DBusMenu {
         root.properties
             .insert("visible", MenuProperty::Visible(true));
         nodes.insert(0, root);
-        Self { nodes }
+        Self {
+            nodes,
+            ids: MenuIdAllocator::default(),
+        }
     }
 
-    fn from_tray_menu_items(items: &[TrayMenuItem]) -> Self {
+    fn from_tray_menu_items(items: &[TrayMenuItem], ids: MenuIdAllocator) -> Self {
         let mut menu = DBusMenu::new();
-        let mut next_id: i32 = 1;
-        for item in items {
-            next_id = menu.add_item(0, item, next_id);
-        }
+        menu.ids = ids;
+        menu.append_items(0, items);
         menu
     }
 
-    fn add_item(&mut self, parent_id: i32, item: &TrayMenuItem, next_id: i32) -> i32 {
+    fn append_items(&mut self, parent_id: i32, items: &[TrayMenuItem]) {
+        let mut key_counts = HashMap::new();
+        let keys: Vec<String> = items
+            .iter()
+            .map(|item| menu_item_key(item, &mut key_counts))
+            .collect();
+        // An empty lazy submenu keeps the layout of its last population, so repopulating it
+        // with the same entries keeps their IDs.
+        let generation = if keys.is_empty() {
+            0
+        } else {
+            self.ids.generation_for(parent_id, &keys)
+        };
+        for (item, key) in items.iter().zip(keys) {
+            let key = match item {
+                TrayMenuItem::Submenu { id: Some(_), .. } => key,
+                _ => format!("{key}@{generation}"),
+            };
+            // A labeled separator is followed by its header row, keyed after the separator.
+            let header = item
+                .section_header()
+                .map(|header| (format!("{key}/header"), header));
+            let id = self.ids.id_for(parent_id, key);
+            self.add_item(parent_id, id, item);
+            if let Some((header_key, header)) = header {
+                let header_id = self.ids.id_for(parent_id, header_key);
+                self.add_item(parent_id, header_id, &header);
+            }
+        }
+    }
+
+    /// Swaps the children of a lazy submenu for freshly populated items. Returns whether the
+    /// host needs to fetch the submenu again: false if the items show the same as before, or
+    /// if the node no longer exists, e.g. because a flush rebuilt the menu in the meantime.
+    fn replace_children(&mut self, parent_id: i32, items: &[TrayMenuItem]) -> bool {
+        let mut new = self.clone();
+        let Some(parent) = new.nodes.get_mut(&parent_id) else {
+            return false;
+        };
+        let mut stale = std::mem::take(&mut parent.children);
+        while let Some(id) = stale.pop() {
+            if let Some(node) = new.nodes.remove(&id) {
+                stale.extend(node.children);
+            }
+        }
+        new.append_items(parent_id, items);
+        new.prune_ids();
+        // Keep the new nodes even if nothing changed, since their click targets carry the
+        // provider's latest closures.
+        let changed = !self.diff(&new).is_empty();
+        *self = new;
+        changed
+    }
+
+    fn prune_ids(&mut self) {
+        self.ids.prune(&self.nodes);
+    }
+
+    fn add_item(&mut self, parent_id: i32, id: i32, item: &TrayMenuItem) {
         match item {
             TrayMenuItem::Separator { visible, .. } => {
-                let id = next_id;
                 let mut node = MenuNode {
                     id,
                     ..Default::default()
@@ -274,63 +533,96 @@ impl DBusMenu {
                 node.properties
                     .insert("visible", MenuProperty::Visible(*visible));
                 self.insert_node(parent_id, node);
-                next_id + 1
             }
             TrayMenuItem::Submenu {
                 label,
-                id: _,
+                id: item_id,
                 enabled,
                 visible,
-                role: _,
+                role,
                 toggle_type,
+                icon,
+                shortcut,
+                mnemonic,
+                payload: _,
+                on_click: _,
+                action: _,
+                provider,
                 children,
             } => {
-                let id = next_id;
+                let parents = self.child_parents(parent_id);
                 let mut node = MenuNode {
                     id,
-                    user_id: item.menu_event_id().map(str::to_owned),
+                    item_id: item_id.clone(),
+                    target: item.menu_target(&parents),
+                    parents,
+                    provider: provider.clone(),
                     ..Default::default()
                 };
                 node.properties
                     .insert("type", MenuProperty::Type("standard"));
-                node.properties
-                    .insert("label", MenuProperty::Label(label.clone()));
+                node.properties.insert(
+                    "label",
+                    MenuProperty::Label(mnemonic_label(label, *mnemonic, '_')),
+                );
                 node.properties
                     .insert("enabled", MenuProperty::Enabled(*enabled));
                 node.properties
                     .insert("visible", MenuProperty::Visible(*visible));
+                if let Some(disposition) = role.disposition() {
+                    node.properties
+                        .insert("disposition", MenuProperty::Disposition(disposition));
+                }
 
                 if let Some(toggle) = toggle_type {
-                    match toggle {
-                        TrayToggleType::Checkbox(checked) => {
-                            node.properties.insert(
-                                "toggle-type",
-                                MenuProperty::ToggleType(MenuToggleType::Checkmark),
-                            );
-                            node.properties.insert(
-                                "toggle-state",
-                                MenuProperty::ToggleState(if *checked { 1 } else { 0 }),
-                            );
-                        }
-                        TrayToggleType::Radio(checked) => {
-                            node.properties.insert(
-                                "toggle-type",
-                                MenuProperty::ToggleType(MenuToggleType::Radio),
-                            );
-                            node.properties.insert(
-                                "toggle-state",
-                                MenuProperty::ToggleState(if *checked { 1 } else { 0 }),
-                            );
+                    let toggle_kind = if toggle.is_radio() {
+                        MenuToggleType::Radio
+                    } else {
+                        MenuToggleType::Checkmark
+                    };
+                    // dbusmenu: 0 = off, 1 = on, anything else = indeterminate.
+                    let toggle_state = match toggle {
+                        TrayToggleType::Indeterminate => -1,
+                        toggle if toggle.is_checked() => 1,
+                        _ => 0,
+                    };
+                    node.properties
+                        .insert("toggle-type", MenuProperty::ToggleType(toggle_kind));
+                    node.properties
+                        .insert("toggle-state", MenuProperty::ToggleState(toggle_state));
+                }
+
+                match icon {
+                    Some(TrayMenuIcon::Image(image)) => {
+                        // An icon that fails to decode shouldn't take the whole menu down.
+                        if let Ok(png) = crate::icon::encode_gpui_image_to_png(image) {
+                            node.properties
+                                .insert("icon-data", MenuProperty::IconData(png));
                         }
                     }
+                    Some(TrayMenuIcon::Named(name)) => {
+                        node.properties
+                            .insert("icon-name", MenuProperty::IconName(name.clone()));
+                    }
+                    None => {}
                 }
 
-                self.insert_node(parent_id, node);
-                let mut next_id = next_id + 1;
-                for child in children {
-                    next_id = self.add_item(id, child, next_id);
+                if !shortcut.is_empty() {
+                    node.properties.insert(
+                        "shortcut",
+                        MenuProperty::Shortcut(dbusmenu_shortcut(shortcut)),
+                    );
+                }
+
+                // Lazy submenus start out empty; hosts only send AboutToShow for nodes that
+                // announce a submenu.
+                if provider.is_some() {
+                    node.properties
+                        .insert("children-display", MenuProperty::ChildrenDisplay("submenu"));
                 }
-                next_id
+
+                self.insert_node(parent_id, node);
+                self.append_items(id, children);
             }
         }
     }
@@ -343,8 +635,128 @@ impl DBusMenu {
         }
     }
 
-    fn user_id_for_node(&self, id: i32) -> Option<String> {
-        self.nodes.get(&id).and_then(|n| n.user_id.clone())
+    /// Keeps the populated children of lazy submenus from the previous revision, so a flush
+    /// doesn't empty a submenu that is currently open. The next AboutToShow refreshes them.
+    fn adopt_lazy_children(&mut self, old: &DBusMenu) {
+        let lazy_ids: Vec<i32> = self
+            .nodes
+            .values()
+            .filter(|node| node.provider.is_some() && node.children.is_empty())
+            .map(|node| node.id)
+            .collect();
+
+        for id in lazy_ids {
+            let Some(old_node) = old.nodes.get(&id).filter(|node| node.provider.is_some()) else {
+                continue;
+            };
+
+            let mut subtree = Vec::new();
+            let mut pending = old_node.children.clone();
+            while let Some(child) = pending.pop() {
+                if let Some(node) = old.nodes.get(&child) {
+                    pending.extend(node.children.iter().copied());
+                    subtree.push(node.clone());
+                }
+            }
+            if subtree.iter().any(|node| self.nodes.contains_key(&node.id)) {
+                continue;
+            }
+
+            for node in subtree {
+                self.nodes.insert(node.id, node);
+            }
+            if let Some(node) = self.nodes.get_mut(&id) {
+                node.children = old_node.children.clone();
+            }
+        }
+    }
+
+    fn diff(&self, new: &DBusMenu) -> DBusMenuDiff {
+        let mut diff = DBusMenuDiff::default();
+        self.diff_node(new, 0, &mut diff);
+        diff
+    }
+
+    fn diff_node(&self, new: &DBusMenu, id: i32, diff: &mut DBusMenuDiff) {
+        let (Some(old_node), Some(new_node)) = (self.nodes.get(&id), new.nodes.get(&id)) else {
+            diff.layout_parents.push(id);
+            return;
+        };
+
+        let updated: HashMap<String, zbus::zvariant::Value<'static>> = new_node
+            .properties
+            .iter()
+            .filter(|(name, property)| old_node.properties.get(*name) != Some(*property))
+            .map(|(name, property)| ((*name).to_string(), property.to_value()))
+            .collect();
+        if !updated.is_empty() {
+            diff.updated_properties.push((id, updated));
+        }
+
+        let mut removed: Vec<String> = old_node
+            .properties
+            .keys()
+            .filter(|name| !new_node.properties.contains_key(*name))
+            .map(|name| (*name).to_string())
+            .collect();
+        if !removed.is_empty() {
+            removed.sort();
+            diff.removed_properties.push((id, removed));
+        }
+
+        if old_node.children != new_node.children {
+            diff.layout_parents.push(id);
+            return;
+        }
+        for child in &new_node.children {
+            self.diff_node(new, *child, diff);
+        }
+    }
+
+    /// The `parents` of a node added under `parent_id`.
+    fn child_parents(&self, parent_id: i32) -> Vec<String> {
+        match self.nodes.get(&parent_id) {
+            Some(parent) if parent_id != 0 => {
+                let mut parents = parent.parents.clone();
+                parents.push(parent.item_id.clone().unwrap_or_default());
+                parents
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn target_for_node(&self, id: i32) -> Option<TrayMenuTarget> {
+        self.nodes.get(&id).and_then(|n| n.target.clone())
+    }
+
+    /// The node of the clickable entry with the given item ID. The first such entry in menu
+    /// order wins when several share the ID.
+    fn node_for_item(&self, item_id: &str) -> Option<&MenuNode> {
+        let mut pending = vec![0];
+        while let Some(id) = pending.pop() {
+            let Some(node) = self.nodes.get(&id) else {
+                continue;
+            };
+            if node.item_id.as_deref() == Some(item_id) && node.target.is_some() {
+                return Some(node);
+            }
+            pending.extend(node.children.iter().rev());
+        }
+        None
+    }
+
+    /// The click target of the entry with the given item ID, if it is currently clickable.
+    fn target_for_item(&self, item_id: &str) -> Option<TrayMenuTarget> {
+        self.node_for_item(item_id)
+            .and_then(|node| node.target.clone())
+    }
+
+    fn item_id_for_node(&self, id: i32) -> Option<String> {
+        self.nodes.get(&id).and_then(|n| n.item_id.clone())
+    }
+
+    fn provider_for_node(&self, id: i32) -> Option<TrayMenuProvider> {
+        self.nodes.get(&id).and_then(|n| n.provider.clone())
     }
 
     fn to_layout(
@@ -475,43 +887,99 @@ impl DBusMenuInterface {
         id: i32,
         event_id: String,
         _event_data: zbus::zvariant::Value<'_>,
-        _timestamp: u32,
+        timestamp: u32,
     ) {
-        self.dispatch_menu_event(id, &event_id);
+        self.dispatch_menu_event(id, &event_id, timestamp);
     }
 
     // Some hosts only send click events through EventGroup.
     async fn event_group(&self, events: Vec<(i32, String, zbus::zvariant::Value<'_>, u32)>) {
-        for (id, event_id, _event_data, _timestamp) in events {
-            self.dispatch_menu_event(id, &event_id);
+        for (id, event_id, _event_data, timestamp) in events {
+            self.dispatch_menu_event(id, &event_id, timestamp);
         }
     }
 
     // Keep click mapping logic in one place so Event and EventGroup behave the same.
-    fn dispatch_menu_event(&self, id: i32, event_id: &str) {
+    fn dispatch_menu_event(&self, id: i32, event_id: &str, timestamp: u32) {
         let event_id_lower = event_id.to_ascii_lowercase();
 
-        // Different hosts use different event ids for activation.
-        let is_activation = matches!(
-            event_id_lower.as_str(),
-            "clicked" | "activate" | "activated" | "toggled"
-        );
-        if !is_activation {
-            return;
+        match event_id_lower.as_str() {
+            "opened" | "closed" => {
+                let item_id = self.menu.lock().ok().and_then(|m| m.item_id_for_node(id));
+                let event = if event_id_lower == "opened" {
+                    LinuxEvent::MenuOpened(item_id)
+                } else {
+                    LinuxEvent::MenuClosed(item_id)
+                };
+                let _ = self.events.send(event);
+                return;
+            }
+            "hovered" => {
+                let item_id = self.menu.lock().ok().and_then(|m| m.item_id_for_node(id));
+                if let Some(item_id) = item_id {
+                    let _ = self.events.send(LinuxEvent::MenuItemHovered(item_id));
+                }
+                return;
+            }
+            // Different hosts use different event ids for activation.
+            "clicked" | "activate" | "activated" | "toggled" => {}
+            _ => return,
         }
 
-        if std::env::var_os("GPUI_TRAY_DEBUG").is_some() {
-            eprintln!("dbusmenu click id={id} event_id={event_id}");
+        // The host may still show an older layout. Node IDs are never reused, so this resolves
+        // to the same entry in the current menu, or to nothing if it was removed or disabled.
+        let target = self.menu.lock().ok().and_then(|m| m.target_for_node(id));
+        if let Some(mut target) = target {
+            target.timestamp = Some(timestamp);
+            let _ = self.events.send(LinuxEvent::MenuClick(target));
         }
+    }
 
-        let user_id = self.menu.lock().ok().and_then(|m| m.user_id_for_node(id));
-        if let Some(user_id) = user_id {
-            let _ = self.events.send(LinuxEvent::MenuClick(user_id));
+    #[zbus(out_args("needUpdate"))]
+    async fn about_to_show(&self, id: i32) -> bool {
+        self.populate_lazy_submenu(id).await
+    }
+
+    #[zbus(out_args("updatesNeeded", "idErrors"))]
+    async fn about_to_show_group(&self, ids: Vec<i32>) -> (Vec<i32>, Vec<i32>) {
+        let mut updates_needed = Vec::new();
+        let mut id_errors = Vec::new();
+        for id in ids {
+            let exists = self
+                .menu
+                .lock()
+                .ok()
+                .is_some_and(|m| m.nodes.contains_key(&id));
+            if !exists {
+                id_errors.push(id);
+            } else if self.populate_lazy_submenu(id).await {
+                updates_needed.push(id);
+            }
         }
+        (updates_needed, id_errors)
     }
 
-    async fn about_to_show(&self, _id: i32) -> bool {
-        false
+    // Runs the provider of a lazy submenu on the main thread and waits until its children
+    // are in place, so the host's follow-up GetLayout already sees them.
+    async fn populate_lazy_submenu(&self, id: i32) -> bool {
+        let provider = self.menu.lock().ok().and_then(|m| m.provider_for_node(id));
+        let Some(provider) = provider else {
+            return false;
+        };
+
+        let (reply, updated) = tokio::sync::oneshot::channel();
+        if self
+            .events
+            .send(LinuxEvent::AboutToShow {
+                id,
+                provider,
+                reply,
+            })
+            .is_err()
+        {
+            return false;
+        }
+        updated.await.unwrap_or(false)
     }
 
     #[zbus(signal, name = "LayoutUpdated")]
@@ -520,6 +988,13 @@ impl DBusMenuInterface {
         revision: u32,
         parent: i32,
     ) -> zbus::Result<()>;
+
+    #[zbus(signal, name = "ItemsPropertiesUpdated")]
+    async fn items_properties_updated(
+        emitter: &zbus::object_server::SignalEmitter<'_>,
+        updated_props: Vec<(i32, HashMap<String, zbus::zvariant::Value<'static>>)>,
+        removed_props: Vec<(i32, Vec<String>)>,
+    ) -> zbus::Result<()>;
 }
 
 #[derive(Debug, Clone, Default)]
@@ -529,9 +1004,41 @@ struct StatusNotifierItemState {
     icon_pixmaps: Vec<Pixmap>,
     tooltip: String,
     description: String,
+    item_is_menu: bool,
+    needs_attention: bool,
+    attention_pixmaps: Vec<Pixmap>,
+    attention_movie: String,
+    overlay_pixmaps: Vec<Pixmap>,
+    overlay_icon_name: String,
+    // The software blink phase; both icons stay decoded so a phase only swaps them.
+    blink_on: bool,
+    // dbusmenu node of `TrayClickPolicy::middle_menu_item`, or 0 if there is none.
+    secondary_activate_target: i32,
+}
+
+impl StatusNotifierItemState {
+    fn shown_icon_pixmaps(&self) -> &[Pixmap] {
+        if self.blink_on {
+            &self.attention_pixmaps
+        } else {
+            &self.icon_pixmaps
+        }
+    }
+
+    fn status(&self) -> &'static str {
+        if !self.visible {
+            "Passive"
+        } else if self.needs_attention {
+            "NeedsAttention"
+        } else {
+            "Active"
+        }
+    }
 }
 
 struct StatusNotifierItemInterface {
+    item_id: String,
+    menu_path: zbus::zvariant::OwnedObjectPath,
     state: Arc<Mutex<StatusNotifierItemState>>,
     events: tokio::sync::mpsc::UnboundedSender<LinuxEvent>,
 }
@@ -545,8 +1052,8 @@ impl StatusNotifierItemInterface {
 
     #[zbus(property, name = "Id")]
     fn id(&self) -> String {
-        // Avoid odd behavior on some trays; keep stable.
-        "gpui-tray".to_string()
+        // Avoid odd behavior on some trays; keep stable for the lifetime of the item.
+        self.item_id.clone()
     }
 
     #[zbus(property, name = "Title")]
@@ -560,12 +1067,11 @@ impl StatusNotifierItemInterface {
 
     #[zbus(property, name = "Status")]
     fn status(&self) -> String {
-        let visible = self.state.lock().ok().map(|s| s.visible).unwrap_or(true);
-        if visible {
-            "Active".to_string()
-        } else {
-            "Passive".to_string()
-        }
+        self.state
+            .lock()
+            .ok()
+            .map_or("Active", |s| s.status())
+            .to_string()
     }
 
     #[zbus(property, name = "IconName")]
@@ -580,7 +1086,48 @@ impl StatusNotifierItemInterface {
         self.state
             .lock()
             .ok()
-            .map(|s| s.icon_pixmaps.clone())
+            .map(|s| s.shown_icon_pixmaps().to_vec())
+            .unwrap_or_default()
+    }
+
+    #[zbus(property, name = "OverlayIconName")]
+    fn overlay_icon_name(&self) -> String {
+        self.state
+            .lock()
+            .ok()
+            .map(|s| s.overlay_icon_name.clone())
+            .unwrap_or_default()
+    }
+
+    #[zbus(property, name = "OverlayIconPixmap")]
+    fn overlay_icon_pixmap(&self) -> Vec<Pixmap> {
+        self.state
+            .lock()
+            .ok()
+            .map(|s| s.overlay_pixmaps.clone())
+            .unwrap_or_default()
+    }
+
+    #[zbus(property, name = "AttentionIconName")]
+    fn attention_icon_name(&self) -> String {
+        String::new()
+    }
+
+    #[zbus(property, name = "AttentionIconPixmap")]
+    fn attention_icon_pixmap(&self) -> Vec<Pixmap> {
+        self.state
+            .lock()
+            .ok()
+            .map(|s| s.attention_pixmaps.clone())
+            .unwrap_or_default()
+    }
+
+    #[zbus(property, name = "AttentionMovieName")]
+    fn attention_movie_name(&self) -> String {
+        self.state
+            .lock()
+            .ok()
+            .map(|s| s.attention_movie.clone())
             .unwrap_or_default()
     }
 
@@ -600,14 +1147,26 @@ impl StatusNotifierItemInterface {
         }
     }
 
+    // Asks the host to open the menu on left click instead of calling Activate.
     #[zbus(property, name = "ItemIsMenu")]
     fn item_is_menu(&self) -> bool {
-        false
+        self.state.lock().ok().is_some_and(|s| s.item_is_menu)
     }
 
     #[zbus(property, name = "Menu")]
     fn menu(&self) -> zbus::zvariant::OwnedObjectPath {
-        zbus::zvariant::OwnedObjectPath::try_from(DBUS_MENU_PATH).expect("valid dbus path")
+        self.menu_path.clone()
+    }
+
+    // Ayatana extension naming the menu entry that a middle click activates, so hosts can
+    // activate it themselves. 0 means middle clicks aren't bound to an entry.
+    #[zbus(property, name = "XAyatanaSecondaryActivateTarget")]
+    fn x_ayatana_secondary_activate_target(&self) -> i32 {
+        self.state
+            .lock()
+            .ok()
+            .map(|s| s.secondary_activate_target)
+            .unwrap_or_default()
     }
 
     async fn activate(&self, x: i32, y: i32) {
@@ -618,6 +1177,17 @@ impl StatusNotifierItemInterface {
         let _ = self.events.send(LinuxEvent::SecondaryActivate(x, y));
     }
 
+    // Ayatana hosts call this instead of SecondaryActivate on middle click.
+    #[zbus(name = "XAyatanaSecondaryActivate")]
+    async fn x_ayatana_secondary_activate(&self, _timestamp: u32) {
+        let _ = self.events.send(LinuxEvent::SecondaryActivate(0, 0));
+    }
+
+    // Only called by hosts that don't show the exported menu themselves.
+    async fn context_menu(&self, x: i32, y: i32) {
+        let _ = self.events.send(LinuxEvent::ContextMenu(x, y));
+    }
+
     async fn scroll(&self, delta: i32, orientation: String) {
         let _ = self.events.send(LinuxEvent::Scroll(delta, orientation));
     }
@@ -628,6 +1198,15 @@ impl StatusNotifierItemInterface {
     #[zbus(signal, name = "NewIcon")]
     async fn new_icon(emitter: &zbus::object_server::SignalEmitter<'_>) -> zbus::Result<()>;
 
+    #[zbus(signal, name = "NewOverlayIcon")]
+    async fn new_overlay_icon(emitter: &zbus::object_server::SignalEmitter<'_>)
+    -> zbus::Result<()>;
+
+    #[zbus(signal, name = "NewAttentionIcon")]
+    async fn new_attention_icon(
+        emitter: &zbus::object_server::SignalEmitter<'_>,
+    ) -> zbus::Result<()>;
+
     #[zbus(signal, name = "NewToolTip")]
     async fn new_tooltip(emitter: &zbus::object_server::SignalEmitter<'_>) -> zbus::Result<()>;
 
@@ -636,21 +1215,48 @@ impl StatusNotifierItemInterface {
         emitter: &zbus::object_server::SignalEmitter<'_>,
         status: String,
     ) -> zbus::Result<()>;
-
-    #[zbus(signal, name = "NewMenu")]
-    async fn new_menu(emitter: &zbus::object_server::SignalEmitter<'_>) -> zbus::Result<()>;
 }
 
 enum Command {
     Flush,
+    Remove,
+}
+
+/// Everything needed to take a tray item off the bus again.
+#[derive(Clone)]
+struct TrayRegistration {
+    connection: zbus::Connection,
+    service: String,
+    menu_path: String,
 }
 
+impl TrayRegistration {
+    async fn unregister(self) {
+        let object_server = self.connection.object_server();
+        let _ = object_server
+            .remove::<StatusNotifierItemInterface, _>(STATUS_NOTIFIER_ITEM_PATH)
+            .await;
+        let _ = object_server
+            .remove::<DBusMenuInterface, _>(self.menu_path.as_str())
+            .await;
+        // Watchers track the bus name, so releasing it is what makes hosts drop the icon.
+        let _ = self.connection.release_name(self.service.as_str()).await;
+    }
+}
+
+type TrayRegistrationSlot = Arc<Mutex<Option<TrayRegistration>>>;
+
 struct LinuxTrayInner {
-    runtime: Mutex<TrayRuntimeState>,
-    callback: TrayEventCallbackSlot,
+    runtime: Arc<Mutex<TrayRuntimeState>>,
     cmd_tx: tokio::sync::mpsc::UnboundedSender<Command>,
 }
 
+impl Drop for LinuxTrayInner {
+    fn drop(&mut self) {
+        let _ = self.cmd_tx.send(Command::Remove);
+    }
+}
+
 #[derive(Clone)]
 pub struct TrayHandle {
     inner: Arc<LinuxTrayInner>,
@@ -658,11 +1264,12 @@ pub struct TrayHandle {
 
 impl TrayHandle {
     pub fn set_state(&self, state: TrayState) -> Result<()> {
+        // A flush that failed stays pending without a queued command, so retry it here.
         let should_flush = self
             .inner
             .runtime
             .lock()
-            .map(|mut runtime| runtime.set_desired_state(state))
+            .map(|mut runtime| runtime.set_desired_state(state) || runtime.has_pending_flush())
             .unwrap_or(false);
 
         if should_flush {
@@ -677,7 +1284,7 @@ impl TrayHandle {
             .inner
             .runtime
             .lock()
-            .map(|mut runtime| runtime.request_flush())
+            .map(|mut runtime| runtime.request_flush() || runtime.has_pending_flush())
             .unwrap_or(false);
 
         if should_flush {
@@ -686,24 +1293,36 @@ impl TrayHandle {
 
         Ok(())
     }
-}
 
-static LINUX_TRAY: OnceLock<TrayHandle> = OnceLock::new();
+    /// Removes the tray item from the host and releases its bus name.
+    ///
+    /// This also happens automatically when the last clone of the handle is dropped.
+    pub fn remove(&self) -> Result<()> {
+        let _ = self.inner.cmd_tx.send(Command::Remove);
+        Ok(())
+    }
+}
 
-fn make_bus_name() -> String {
+fn make_bus_name(instance: u32) -> String {
     // Format inspired by common implementations; must be a unique well-formed bus name.
     // (No ':' here; that's for unique names assigned by the bus.)
     let pid = std::process::id();
-    let nanos = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .map(|d| d.subsec_nanos())
-        .unwrap_or(0);
     format!(
         "org.freedesktop.StatusNotifierItem.gpui_tray_{}_{}",
-        pid, nanos
+        pid, instance
     )
 }
 
+fn make_item_id(instance: u32) -> String {
+    // Hosts persist per-item settings (e.g. hidden/shown) by Id, so the first tray keeps the
+    // historical Id.
+    if instance == 0 {
+        "gpui-tray".to_string()
+    } else {
+        format!("gpui-tray-{instance}")
+    }
+}
+
 async fn register_with_watcher(connection: &zbus::Connection, service: &str) -> zbus::Result<()> {
     let proxy = zbus::Proxy::new(
         connection,
@@ -725,43 +1344,66 @@ pub fn set_up_tray(
     initial: TrayState,
     on_event: TrayEventCallback,
 ) -> Result<TrayHandle> {
-    if LINUX_TRAY.get().is_some() {
-        anyhow::bail!("tray already initialized");
-    }
+    let instance = NEXT_TRAY_INSTANCE.fetch_add(1, Ordering::Relaxed);
+    let service = make_bus_name(instance);
+    let menu_path = format!("{DBUS_MENU_PATH}/{instance}");
+    let menu_object_path = zbus::zvariant::OwnedObjectPath::try_from(menu_path.clone())
+        .context("invalid dbusmenu object path")?;
 
-    let callback = Arc::new(Mutex::new(Some(on_event)));
+    let callback: TrayEventCallbackSlot = Arc::new(Mutex::new(Some(on_event)));
     let (cmd_tx, mut cmd_rx) = tokio::sync::mpsc::unbounded_channel::<Command>();
 
     // Event fan-in for Activate/Scroll/Menu clicks from DBus interfaces.
     let (event_tx, mut event_rx) = tokio::sync::mpsc::unbounded_channel::<LinuxEvent>();
 
+    let runtime = Arc::new(Mutex::new(TrayRuntimeState::new(initial)));
     let state = Arc::new(Mutex::new(StatusNotifierItemState::default()));
     let click_policy = Arc::new(Mutex::new(TrayClickPolicy::default()));
     let menu = Arc::new(Mutex::new(DBusMenu::new()));
     let revision = Arc::new(AtomicU32::new(1));
+    let registration: TrayRegistrationSlot = Arc::new(Mutex::new(None));
+
+    // The quit path blocks the main thread, so it cannot wait for the event loop below and
+    // unregisters directly instead.
+    let quit_subscription = cx.on_app_quit({
+        let registration = registration.clone();
+        let cmd_tx = cmd_tx.clone();
+        move |_| {
+            let registration = registration.lock().ok().and_then(|mut slot| slot.take());
+            let _ = cmd_tx.send(Command::Remove);
+            async move {
+                if let Some(registration) = registration {
+                    registration.unregister().await;
+                }
+            }
+        }
+    });
+
+    QUIT_SUBSCRIPTIONS.with(|subscriptions| {
+        subscriptions
+            .borrow_mut()
+            .insert(instance, quit_subscription)
+    });
+    let quit_subscription_guard = QuitSubscriptionGuard(instance);
 
     let handle = TrayHandle {
         inner: Arc::new(LinuxTrayInner {
-            runtime: Mutex::new(TrayRuntimeState::new(initial)),
-            callback: callback.clone(),
+            runtime: runtime.clone(),
             cmd_tx: cmd_tx.clone(),
         }),
     };
 
-    LINUX_TRAY
-        .set(handle.clone())
-        .map_err(|_| anyhow::anyhow!("tray storage already initialized"))?;
+    // Weak so the loop doesn't keep its own command channel open.
+    let flush_tx = cmd_tx.downgrade();
 
     async_app
         .spawn(move |cx: &mut AsyncApp| {
             let async_app = cx.clone();
-            let callback = callback.clone();
-            let handle = handle.clone();
-            let click_policy = click_policy.clone();
             async move {
-                let service = make_bus_name();
-
+                let _quit_subscription_guard = quit_subscription_guard;
                 let status_iface = StatusNotifierItemInterface {
+                    item_id: make_item_id(instance),
+                    menu_path: menu_object_path,
                     state: state.clone(),
                     events: event_tx.clone(),
                 };
@@ -787,7 +1429,7 @@ pub fn set_up_tray(
                     return;
                 };
 
-                let builder = builder.serve_at(DBUS_MENU_PATH, menu_iface);
+                let builder = builder.serve_at(menu_path.as_str(), menu_iface);
                 let Ok(builder) = builder else {
                     return;
                 };
@@ -799,6 +1441,14 @@ pub fn set_up_tray(
 
                 let _ = register_with_watcher(&connection, &service).await;
 
+                if let Ok(mut slot) = registration.lock() {
+                    *slot = Some(TrayRegistration {
+                        connection: connection.clone(),
+                        service: service.clone(),
+                        menu_path: menu_path.clone(),
+                    });
+                }
+
                 let status_ref = connection
                     .object_server()
                     .interface::<_, StatusNotifierItemInterface>(STATUS_NOTIFIER_ITEM_PATH)
@@ -806,17 +1456,42 @@ pub fn set_up_tray(
                     .ok();
                 let menu_ref = connection
                     .object_server()
-                    .interface::<_, DBusMenuInterface>(DBUS_MENU_PATH)
+                    .interface::<_, DBusMenuInterface>(menu_path.as_str())
                     .await
                     .ok();
 
+                // Menu clicks, and middle clicks bound to a menu entry, flip the entry locally
+                // before running it.
+                let activate_menu_target = |mut target: TrayMenuTarget| {
+                    target.checked = runtime.lock().ok().and_then(|mut runtime| {
+                        runtime.refresh_target(&mut target);
+                        runtime.toggle_menu_item(&target.parents, &target.id)
+                    });
+                    if target.checked.is_some()
+                        && let Some(flush_tx) = flush_tx.upgrade()
+                    {
+                        let _ = flush_tx.send(Command::Flush);
+                    }
+                    dispatch_menu_target(&async_app, &callback, target);
+                };
+
+                let mut clicks = ClickTracker::default();
+                let mut next_blink: Option<Instant> = None;
                 loop {
+                    let blinking = runtime.lock().is_ok_and(|runtime| runtime.is_blinking());
+                    if !blinking {
+                        next_blink = None;
+                    } else if next_blink.is_none() {
+                        next_blink = Some(Instant::now() + ATTENTION_BLINK_INTERVAL);
+                    }
+
                     tokio::select! {
                         Some(cmd) = cmd_rx.recv() => {
                             match cmd {
                                 Command::Flush => {
                                     let _ = flush_linux_runtime(
-                                        &handle,
+                                        &async_app,
+                                        &runtime,
                                         &state,
                                         &click_policy,
                                         &menu,
@@ -826,18 +1501,70 @@ pub fn set_up_tray(
                                     )
                                     .await;
                                 }
+                                Command::Remove => {
+                                    let registration =
+                                        registration.lock().ok().and_then(|mut slot| slot.take());
+                                    if let Some(registration) = registration {
+                                        registration.unregister().await;
+                                    }
+                                    break;
+                                }
                             }
                         }
                         Some(ev) = event_rx.recv() => {
-                            let policy = click_policy.lock().ok().map(|policy| *policy).unwrap_or_default();
+                            // Any interaction means the user noticed the item.
+                            let interaction = matches!(
+                                ev,
+                                LinuxEvent::Activate(..)
+                                    | LinuxEvent::SecondaryActivate(..)
+                                    | LinuxEvent::ContextMenu(..)
+                                    | LinuxEvent::MenuOpened(_)
+                                    | LinuxEvent::MenuClick(_)
+                            );
+                            if interaction
+                                && runtime.lock().is_ok_and(|mut runtime| runtime.stop_blink())
+                                && let Some(flush_tx) = flush_tx.upgrade()
+                            {
+                                let _ = flush_tx.send(Command::Flush);
+                            }
+                            let policy = click_policy.lock().ok().map(|policy| policy.clone()).unwrap_or_default();
                             let event = match ev {
-                                LinuxEvent::Activate(x,y) => map_click_event(
-                                    policy.left,
-                                    MouseButton::Left,
-                                    TrayClickKind::Single,
-                                    Point { x, y },
-                                ),
-                                LinuxEvent::SecondaryActivate(x,y) => map_click_event(
+                                LinuxEvent::Activate(x, y) => {
+                                    let position = Point { x, y };
+                                    match clicks.click(position, &policy, Instant::now()) {
+                                        Some(TrayClickKind::Double) => map_click_event(
+                                            policy.double_click,
+                                            MouseButton::Left,
+                                            TrayClickKind::Double,
+                                            position,
+                                        ),
+                                        Some(TrayClickKind::Single) => map_click_event(
+                                            policy.left,
+                                            MouseButton::Left,
+                                            TrayClickKind::Single,
+                                            position,
+                                        ),
+                                        None => None,
+                                    }
+                                }
+                                LinuxEvent::SecondaryActivate(x, y) => {
+                                    let target = policy.middle_menu_item.as_deref().and_then(|id| {
+                                        menu.lock().ok().and_then(|m| m.target_for_item(id))
+                                    });
+                                    match target {
+                                        Some(target) => {
+                                            activate_menu_target(target);
+                                            None
+                                        }
+                                        None => map_click_event(
+                                            policy.middle,
+                                            MouseButton::Middle,
+                                            TrayClickKind::Single,
+                                            Point { x, y },
+                                        ),
+                                    }
+                                }
+                                LinuxEvent::ContextMenu(x, y) => map_click_event(
                                     policy.right,
                                     MouseButton::Right,
                                     TrayClickKind::Single,
@@ -852,12 +1579,66 @@ pub fn set_up_tray(
                                     };
                                     Some(TrayEvent::Scroll { scroll_detal })
                                 }
-                                LinuxEvent::MenuClick(id) => Some(TrayEvent::MenuClick { id }),
+                                LinuxEvent::MenuClick(target) => {
+                                    activate_menu_target(target);
+                                    None
+                                }
+                                LinuxEvent::MenuOpened(id) => Some(TrayEvent::MenuOpened { id }),
+                                LinuxEvent::MenuClosed(id) => Some(TrayEvent::MenuClosed { id }),
+                                LinuxEvent::MenuItemHovered(id) => {
+                                    Some(TrayEvent::MenuItemHovered { id })
+                                }
+                                LinuxEvent::AboutToShow { id, provider, reply } => {
+                                    let provider = match runtime.lock() {
+                                        Ok(runtime) => runtime.refresh_provider(provider),
+                                        Err(_) => provider,
+                                    };
+                                    let items = async_app.update(|cx| provider.populate(cx));
+                                    let updated = menu
+                                        .lock()
+                                        .is_ok_and(|mut m| m.replace_children(id, &items));
+                                    if updated {
+                                        let rev = revision.fetch_add(1, Ordering::Relaxed).saturating_add(1);
+                                        if let Some(menu_ref) = menu_ref.as_ref() {
+                                            let emitter = menu_ref.signal_emitter();
+                                            let _ = DBusMenuInterface::layout_updated(emitter, rev, id).await;
+                                        }
+                                    }
+                                    let _ = reply.send(updated);
+                                    None
+                                }
                             };
                             if let Some(event) = event {
                                 dispatch_event(&async_app, &callback, event);
                             }
                         }
+                        _ = async_app.background_executor().timer(
+                            clicks.pending_delay(Instant::now()).unwrap_or_default()
+                        ), if clicks.pending.is_some() => {
+                            let policy = click_policy.lock().ok().map(|policy| policy.clone()).unwrap_or_default();
+                            if let Some(position) = clicks.take_due(Instant::now())
+                                && let Some(event) = map_click_event(
+                                    policy.left,
+                                    MouseButton::Left,
+                                    TrayClickKind::Single,
+                                    position,
+                                )
+                            {
+                                dispatch_event(&async_app, &callback, event);
+                            }
+                        }
+                        _ = async_app.background_executor().timer(
+                            next_blink
+                                .map(|due| due.saturating_duration_since(Instant::now()))
+                                .unwrap_or_default()
+                        ), if next_blink.is_some() => {
+                            next_blink = None;
+                            if runtime.lock().is_ok_and(|mut runtime| runtime.advance_blink())
+                                && let Some(flush_tx) = flush_tx.upgrade()
+                            {
+                                let _ = flush_tx.send(Command::Flush);
+                            }
+                        }
                         else => break,
                     }
                 }
@@ -866,10 +1647,62 @@ pub fn set_up_tray(
         .detach();
 
     let _ = handle.inner.cmd_tx.send(Command::Flush);
-    let _ = cx;
     Ok(handle)
 }
 
+/// Turns the host's `Activate` calls into single and double clicks; the StatusNotifierItem
+/// spec has no double-click event.
+#[derive(Debug, Default)]
+struct ClickTracker {
+    last_click: Option<Instant>,
+    // A single click held back by `TrayClickPolicy::delay_single_click`, and when it is due.
+    pending: Option<(Point<i32>, Instant)>,
+}
+
+impl ClickTracker {
+    /// Records a click and returns the one to report right away, if any.
+    fn click(
+        &mut self,
+        position: Point<i32>,
+        policy: &TrayClickPolicy,
+        now: Instant,
+    ) -> Option<TrayClickKind> {
+        let is_double = self
+            .last_click
+            .take()
+            .is_some_and(|last| now.duration_since(last) <= policy.double_click_interval);
+        if is_double {
+            self.pending = None;
+            return Some(TrayClickKind::Double);
+        }
+
+        self.last_click = Some(now);
+        if policy.delay_single_click {
+            self.pending = Some((position, now + policy.double_click_interval));
+            None
+        } else {
+            Some(TrayClickKind::Single)
+        }
+    }
+
+    /// How long until the held-back single click is due.
+    fn pending_delay(&self, now: Instant) -> Option<Duration> {
+        self.pending
+            .map(|(_, due)| due.saturating_duration_since(now))
+    }
+
+    /// Takes the held-back single click once it is due.
+    fn take_due(&mut self, now: Instant) -> Option<Point<i32>> {
+        match self.pending {
+            Some((position, due)) if due <= now => {
+                self.pending = None;
+                Some(position)
+            }
+            _ => None,
+        }
+    }
+}
+
 fn map_click_event(
     action: TrayClickAction,
     button: MouseButton,
@@ -882,12 +1715,16 @@ fn map_click_event(
             kind,
             position,
         }),
+        // Hosts that honor ItemIsMenu open the menu without calling Activate; the item can't
+        // show its exported menu itself, so there is nothing left to do here.
         TrayClickAction::OpenMenu | TrayClickAction::Ignore => None,
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn flush_linux_runtime(
-    handle: &TrayHandle,
+    async_app: &AsyncApp,
+    runtime: &Mutex<TrayRuntimeState>,
     state: &Arc<Mutex<StatusNotifierItemState>>,
     click_policy: &Arc<Mutex<TrayClickPolicy>>,
     menu: &Arc<Mutex<DBusMenu>>,
@@ -896,9 +1733,7 @@ async fn flush_linux_runtime(
     menu_ref: Option<&zbus::object_server::InterfaceRef<DBusMenuInterface>>,
 ) -> Result<()> {
     loop {
-        let versioned_state = handle
-            .inner
-            .runtime
+        let versioned_state = runtime
             .lock()
             .ok()
             .and_then(|mut runtime| runtime.try_begin_flush());
@@ -906,9 +1741,20 @@ async fn flush_linux_runtime(
         let Some(versioned_state) = versioned_state else {
             return Ok(());
         };
+        // The diff and the applied state stay those of the state the app set; availability
+        // only shapes what is shown.
+        let diff = runtime
+            .lock()
+            .ok()
+            .map(|runtime| runtime.flush_diff(&versioned_state))
+            .unwrap_or_else(TrayStateDiff::all);
+        let mut shown_state = versioned_state.state.clone();
+        async_app.update(|cx| shown_state.resolve_action_availability(cx));
 
         let apply_result = apply_linux_state(
-            &versioned_state,
+            &shown_state,
+            versioned_state.blink_on,
+            &diff,
             state,
             click_policy,
             menu,
@@ -918,9 +1764,7 @@ async fn flush_linux_runtime(
         )
         .await;
 
-        let should_continue = handle
-            .inner
-            .runtime
+        let should_continue = runtime
             .lock()
             .map(|mut runtime| {
                 if apply_result.is_ok() {
@@ -940,8 +1784,11 @@ async fn flush_linux_runtime(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn apply_linux_state(
-    versioned_state: &VersionedTrayState,
+    tray_state: &TrayState,
+    blink_on: bool,
+    diff: &TrayStateDiff,
     state: &Arc<Mutex<StatusNotifierItemState>>,
     click_policy: &Arc<Mutex<TrayClickPolicy>>,
     menu: &Arc<Mutex<DBusMenu>>,
@@ -949,45 +1796,634 @@ async fn apply_linux_state(
     status_ref: Option<&zbus::object_server::InterfaceRef<StatusNotifierItemInterface>>,
     menu_ref: Option<&zbus::object_server::InterfaceRef<DBusMenuInterface>>,
 ) -> Result<()> {
-    let update = linux_item_from_tray_state(versioned_state.state.clone())
+    if diff.is_empty() {
+        return Ok(());
+    }
+
+    let menu_ids = menu.lock().ok().map(|m| m.ids.clone()).unwrap_or_default();
+    let update = linux_item_from_tray_state(tray_state.clone(), diff, menu_ids)
         .context("failed to build linux tray payload")?;
 
+    let item_is_menu = update.click_policy.left == TrayClickAction::OpenMenu;
+    let mut item_is_menu_changed = false;
+    let mut status = "Active";
     if let Ok(mut s) = state.lock() {
+        item_is_menu_changed = s.item_is_menu != item_is_menu;
+        s.item_is_menu = item_is_menu;
         s.visible = update.visible;
+        s.needs_attention = update.needs_attention;
+        if let Some(attention_pixmaps) = update.attention_pixmaps {
+            s.attention_pixmaps = attention_pixmaps;
+        }
+        s.attention_movie = update.attention_movie;
+        if let Some(overlay_pixmaps) = update.overlay_pixmaps {
+            s.overlay_pixmaps = overlay_pixmaps;
+        }
+        s.overlay_icon_name = update.overlay_icon_name;
+        s.blink_on = blink_on;
+        status = s.status();
         s.title = update.title;
-        s.icon_pixmaps = update.icon_pixmaps;
+        if let Some(icon_pixmaps) = update.icon_pixmaps {
+            s.icon_pixmaps = icon_pixmaps;
+        }
         s.tooltip = update.tooltip;
         s.description = update.description;
     }
+    let middle_menu_item = update.click_policy.middle_menu_item.clone();
     if let Ok(mut policy) = click_policy.lock() {
         *policy = update.click_policy;
     }
-    if let Ok(mut m) = menu.lock() {
-        *m = update.menu;
-    }
-    let rev = revision.fetch_add(1, Ordering::Relaxed).saturating_add(1);
+    // Only changed properties and restructured subtrees are announced, so hosts don't close
+    // or redraw an open menu when, say, a checkbox toggles.
+    let menu_diff = match (update.menu, menu.lock()) {
+        (Some(mut new_menu), Ok(mut m)) => {
+            new_menu.adopt_lazy_children(&m);
+            new_menu.prune_ids();
+            let diff = m.diff(&new_menu);
+            *m = new_menu;
+            diff
+        }
+        _ => DBusMenuDiff::default(),
+    };
+    let secondary_activate_target = menu
+        .lock()
+        .ok()
+        .and_then(|m| {
+            let node = m.node_for_item(middle_menu_item.as_deref()?)?;
+            Some(node.id)
+        })
+        .unwrap_or_default();
+    let secondary_activate_target_changed = state.lock().is_ok_and(|mut s| {
+        let changed = s.secondary_activate_target != secondary_activate_target;
+        s.secondary_activate_target = secondary_activate_target;
+        changed
+    });
 
+    // Every signal makes hosts re-fetch the matching properties, so only send what changed.
     if let Some(status_ref) = status_ref {
         let emitter = status_ref.signal_emitter();
-        let _ = StatusNotifierItemInterface::new_title(emitter).await;
-        let _ = StatusNotifierItemInterface::new_icon(emitter).await;
-        let _ = StatusNotifierItemInterface::new_tooltip(emitter).await;
-        let _ = StatusNotifierItemInterface::new_status(emitter, {
-            let visible = state.lock().ok().map(|s| s.visible).unwrap_or(true);
-            if visible {
-                "Active".to_string()
-            } else {
-                "Passive".to_string()
-            }
-        })
-        .await;
-        let _ = StatusNotifierItemInterface::new_menu(emitter).await;
+        if diff.title {
+            let _ = StatusNotifierItemInterface::new_title(emitter).await;
+        }
+        if diff.icon || diff.blink {
+            let _ = StatusNotifierItemInterface::new_icon(emitter).await;
+        }
+        // The tooltip embeds the regular icon's pixmaps.
+        if diff.tooltip || diff.description || diff.icon {
+            let _ = StatusNotifierItemInterface::new_tooltip(emitter).await;
+        }
+        if diff.overlay {
+            let _ = StatusNotifierItemInterface::new_overlay_icon(emitter).await;
+        }
+        if diff.attention {
+            let _ = StatusNotifierItemInterface::new_attention_icon(emitter).await;
+        }
+        if diff.visible || diff.attention {
+            let _ = StatusNotifierItemInterface::new_status(emitter, status.to_string()).await;
+        }
+        // ItemIsMenu has no dedicated SNI signal; PropertiesChanged is what hosts watch.
+        if item_is_menu_changed {
+            let _ = status_ref.get().await.item_is_menu_changed(emitter).await;
+        }
+        if secondary_activate_target_changed {
+            let _ = status_ref
+                .get()
+                .await
+                .x_ayatana_secondary_activate_target_changed(emitter)
+                .await;
+        }
     }
 
-    if let Some(menu_ref) = menu_ref {
+    if let Some(menu_ref) = menu_ref
+        && !menu_diff.is_empty()
+    {
         let emitter = menu_ref.signal_emitter();
-        let _ = DBusMenuInterface::layout_updated(emitter, rev, 0).await;
+        if !menu_diff.updated_properties.is_empty() || !menu_diff.removed_properties.is_empty() {
+            let _ = DBusMenuInterface::items_properties_updated(
+                emitter,
+                menu_diff.updated_properties,
+                menu_diff.removed_properties,
+            )
+            .await;
+        }
+        if !menu_diff.layout_parents.is_empty() {
+            let rev = revision.fetch_add(1, Ordering::Relaxed).saturating_add(1);
+            for parent in menu_diff.layout_parents {
+                let _ = DBusMenuInterface::layout_updated(emitter, rev, parent).await;
+            }
+        }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ClickTracker, DBusMenu, DBusMenuInterface, LinuxEvent, MenuIdAllocator, MenuProperty,
+        StatusNotifierItemInterface, StatusNotifierItemState, apply_linux_state, dbusmenu_key_name,
+        dbusmenu_shortcut,
+    };
+    use crate::tray::{
+        TrayClickKind, TrayClickPolicy, TrayMenuItem, TrayMenuItemRole, TrayState, TrayStateDiff,
+    };
+    use gpui::{Keystroke, Modifiers, Point};
+    use std::sync::{Arc, Mutex, atomic::AtomicU32};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn menu_diff_reports_toggled_checkbox_as_property_update() {
+        let old = DBusMenu::from_tray_menu_items(
+            &[
+                TrayMenuItem::checkbox("wifi", "Wi-Fi", false),
+                TrayMenuItem::menu("quit", "Quit", Vec::new()),
+            ],
+            MenuIdAllocator::default(),
+        );
+        let new = DBusMenu::from_tray_menu_items(
+            &[
+                TrayMenuItem::checkbox("wifi", "Wi-Fi", true),
+                TrayMenuItem::menu("quit", "Quit", Vec::new()),
+            ],
+            old.ids.clone(),
+        );
+
+        let diff = old.diff(&new);
+        assert!(diff.layout_parents.is_empty());
+        assert!(diff.removed_properties.is_empty());
+        assert_eq!(diff.updated_properties.len(), 1);
+        let (id, properties) = &diff.updated_properties[0];
+        assert_eq!(*id, 1);
+        assert_eq!(properties.keys().collect::<Vec<_>>(), vec!["toggle-state"]);
+        assert!(old.diff(&old.clone()).is_empty());
+    }
+
+    #[test]
+    fn menu_diff_limits_layout_update_to_changed_subtree() {
+        let old = DBusMenu::from_tray_menu_items(
+            &[
+                TrayMenuItem::menu("quit", "Quit", Vec::new()),
+                TrayMenuItem::menu(
+                    "devices",
+                    "Devices",
+                    vec![TrayMenuItem::menu("a", "A", Vec::new())],
+                ),
+            ],
+            MenuIdAllocator::default(),
+        );
+        let new = DBusMenu::from_tray_menu_items(
+            &[
+                TrayMenuItem::menu("quit", "Quit", Vec::new()),
+                TrayMenuItem::menu(
+                    "devices",
+                    "Devices",
+                    vec![
+                        TrayMenuItem::menu("a", "A", Vec::new()),
+                        TrayMenuItem::menu("b", "B", Vec::new()),
+                    ],
+                ),
+            ],
+            old.ids.clone(),
+        );
+
+        let diff = old.diff(&new);
+        assert_eq!(diff.layout_parents, vec![2]);
+        assert!(diff.updated_properties.is_empty());
+    }
+
+    #[test]
+    fn menu_node_ids_survive_insertions() {
+        let old = DBusMenu::from_tray_menu_items(
+            &[
+                TrayMenuItem::menu("open", "Open", Vec::new()),
+                TrayMenuItem::menu("quit", "Quit", Vec::new()),
+            ],
+            MenuIdAllocator::default(),
+        );
+        let new = DBusMenu::from_tray_menu_items(
+            &[
+                TrayMenuItem::menu("status", "Status", Vec::new()),
+                TrayMenuItem::menu("quit", "Quit", Vec::new()),
+            ],
+            old.ids.clone(),
+        );
+
+        let id_of = |menu: &DBusMenu, item_id: &str| {
+            menu.nodes
+                .values()
+                .find(|node| node.item_id.as_deref() == Some(item_id))
+                .map(|node| node.id)
+        };
+        assert_eq!(id_of(&old, "quit"), id_of(&new, "quit"));
+        let removed = id_of(&old, "open").unwrap();
+        assert!(new.target_for_node(removed).is_none());
+        assert_ne!(id_of(&new, "status"), Some(removed));
+    }
+
+    #[test]
+    fn click_for_shifted_entry_without_id_is_dropped() {
+        let node_for = |menu: &DBusMenu, label: &str| {
+            menu.nodes
+                .values()
+                .find(|node| node.target.as_ref().is_some_and(|t| t.label == label))
+                .map(|node| node.id)
+                .unwrap()
+        };
+        let old = DBusMenu::from_tray_menu_items(
+            &[
+                TrayMenuItem::typed("Connect", 1u32),
+                TrayMenuItem::menu("quit", "Quit", Vec::new()),
+            ],
+            MenuIdAllocator::default(),
+        );
+        let mut new = DBusMenu::from_tray_menu_items(
+            &[
+                TrayMenuItem::typed("Disconnect", 2u32),
+                TrayMenuItem::typed("Connect", 1u32),
+                TrayMenuItem::menu("quit", "Quit", Vec::new()),
+            ],
+            old.ids.clone(),
+        );
+        new.prune_ids();
+        let (stale, current) = (node_for(&old, "Connect"), node_for(&new, "Connect"));
+        assert_ne!(node_for(&new, "Disconnect"), stale);
+
+        let (events, mut received) = tokio::sync::mpsc::unbounded_channel();
+        let interface = DBusMenuInterface {
+            menu: Arc::new(Mutex::new(new)),
+            revision: Arc::new(AtomicU32::new(2)),
+            events,
+        };
+        // The host still shows the old layout, where this node was "Connect".
+        interface.dispatch_menu_event(stale, "clicked", 0);
+        assert!(received.try_recv().is_err());
+
+        interface.dispatch_menu_event(current, "clicked", 0);
+        let Ok(LinuxEvent::MenuClick(target)) = received.try_recv() else {
+            panic!("click on the current layout was dropped");
+        };
+        assert_eq!(target.label, "Connect");
+    }
+
+    #[test]
+    fn secondary_activate_target_exports_middle_click_entry() {
+        let (events, _received) = tokio::sync::mpsc::unbounded_channel();
+        let interface = StatusNotifierItemInterface {
+            item_id: "test".to_string(),
+            menu_path: zbus::zvariant::OwnedObjectPath::try_from("/MenuBar").unwrap(),
+            state: Arc::new(Mutex::new(StatusNotifierItemState::default())),
+            events,
+        };
+        let click_policy = Arc::new(Mutex::new(TrayClickPolicy::platform_default()));
+        let menu = Arc::new(Mutex::new(DBusMenu::new()));
+        let revision = Arc::new(AtomicU32::new(1));
+        let executor = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let apply = |tray_state: TrayState| {
+            executor
+                .block_on(apply_linux_state(
+                    &tray_state,
+                    false,
+                    &TrayStateDiff::all(),
+                    &interface.state,
+                    &click_policy,
+                    &menu,
+                    &revision,
+                    None,
+                    None,
+                ))
+                .unwrap();
+            interface.x_ayatana_secondary_activate_target()
+        };
+        let node_of = |item_id: &str| node_id(&menu.lock().unwrap(), item_id).unwrap();
+
+        let state = TrayState::new()
+            .submenu(TrayMenuItem::checkbox("mute", "Mute", false))
+            .submenu(TrayMenuItem::menu("quit", "Quit", Vec::new()));
+        assert_eq!(apply(state.clone()), 0);
+
+        let middle = |id: &str| TrayClickPolicy::platform_default().middle_menu_item(id);
+        assert_eq!(
+            apply(state.clone().click_policy(middle("mute"))),
+            node_of("mute")
+        );
+        assert_eq!(
+            apply(state.clone().click_policy(middle("quit"))),
+            node_of("quit")
+        );
+        // An entry that isn't in the menu exports no target.
+        assert_eq!(apply(state.click_policy(middle("missing"))), 0);
+    }
+
+    #[test]
+    fn tray_handle_can_move_across_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<super::TrayHandle>();
+    }
+
+    #[test]
+    fn menu_diff_reports_relabeled_entry_without_id_as_property_update() {
+        let old = DBusMenu::from_tray_menu_items(
+            &[
+                TrayMenuItem::info("Latency: 1 ms"),
+                TrayMenuItem::menu("quit", "Quit", Vec::new()),
+            ],
+            MenuIdAllocator::default(),
+        );
+        let mut new = DBusMenu::from_tray_menu_items(
+            &[
+                TrayMenuItem::info("Latency: 2 ms"),
+                TrayMenuItem::menu("quit", "Quit", Vec::new()),
+            ],
+            old.ids.clone(),
+        );
+        new.prune_ids();
+
+        let diff = old.diff(&new);
+        assert!(diff.layout_parents.is_empty());
+        assert_eq!(diff.updated_properties.len(), 1);
+        let (id, properties) = &diff.updated_properties[0];
+        assert_eq!(*id, 1);
+        assert_eq!(properties.keys().collect::<Vec<_>>(), vec!["label"]);
+        assert_eq!(new.ids.ids.len(), 2);
+    }
+
+    #[test]
+    fn menu_id_allocator_prunes_removed_entries() {
+        let old = DBusMenu::from_tray_menu_items(
+            &[
+                TrayMenuItem::menu("open", "Open", Vec::new()),
+                TrayMenuItem::menu("quit", "Quit", Vec::new()),
+            ],
+            MenuIdAllocator::default(),
+        );
+        let mut new = DBusMenu::from_tray_menu_items(
+            &[TrayMenuItem::menu("quit", "Quit", Vec::new())],
+            old.ids.clone(),
+        );
+        new.prune_ids();
+        assert_eq!(new.ids.ids.len(), 1);
+
+        let readded = DBusMenu::from_tray_menu_items(
+            &[
+                TrayMenuItem::menu("open", "Open", Vec::new()),
+                TrayMenuItem::menu("quit", "Quit", Vec::new()),
+            ],
+            new.ids.clone(),
+        );
+        let open_id = |menu: &DBusMenu| {
+            menu.nodes
+                .values()
+                .find(|node| node.item_id.as_deref() == Some("open"))
+                .map(|node| node.id)
+        };
+        assert_eq!(open_id(&old), Some(1));
+        assert_eq!(open_id(&readded), Some(3));
+    }
+
+    fn lazy_menu() -> DBusMenu {
+        DBusMenu::from_tray_menu_items(
+            &[TrayMenuItem::lazy("recent", "Recent", |_| Vec::new())],
+            MenuIdAllocator::default(),
+        )
+    }
+
+    fn node_id(menu: &DBusMenu, item_id: &str) -> Option<i32> {
+        menu.nodes
+            .values()
+            .find(|node| node.item_id.as_deref() == Some(item_id))
+            .map(|node| node.id)
+    }
+
+    #[test]
+    fn lazy_submenu_keeps_ids_across_repopulation() {
+        let mut menu = lazy_menu();
+        let recent = node_id(&menu, "recent").unwrap();
+        assert!(menu.replace_children(
+            recent,
+            &[
+                TrayMenuItem::menu("a", "A", Vec::new()),
+                TrayMenuItem::menu("b", "B", Vec::new()),
+            ],
+        ));
+        let (a, b) = (node_id(&menu, "a").unwrap(), node_id(&menu, "b").unwrap());
+
+        assert!(menu.replace_children(
+            recent,
+            &[
+                TrayMenuItem::menu("b", "B", Vec::new()),
+                TrayMenuItem::menu("c", "C", Vec::new()),
+            ],
+        ));
+        assert_eq!(node_id(&menu, "b"), Some(b));
+        assert!(menu.target_for_node(a).is_none());
+        assert_ne!(node_id(&menu, "c"), Some(a));
+
+        // Repopulating with the same entries needs no update.
+        assert!(!menu.replace_children(
+            recent,
+            &[
+                TrayMenuItem::menu("b", "B", Vec::new()),
+                TrayMenuItem::menu("c", "C", Vec::new()),
+            ],
+        ));
+        assert_eq!(node_id(&menu, "b"), Some(b));
+        assert!(!menu.replace_children(i32::MAX, &[]));
+    }
+
+    #[test]
+    fn flush_keeps_populated_lazy_children() {
+        let mut old = lazy_menu();
+        let recent = node_id(&old, "recent").unwrap();
+        old.replace_children(recent, &[TrayMenuItem::menu("a", "A", Vec::new())]);
+        let a = node_id(&old, "a").unwrap();
+
+        let mut new = DBusMenu::from_tray_menu_items(
+            &[TrayMenuItem::lazy("recent", "Recent", |_| Vec::new())],
+            old.ids.clone(),
+        );
+        new.adopt_lazy_children(&old);
+        new.prune_ids();
+        assert_eq!(node_id(&new, "a"), Some(a));
+        assert!(old.diff(&new).layout_parents.is_empty());
+
+        new.replace_children(recent, &[TrayMenuItem::menu("a", "A", Vec::new())]);
+        assert_eq!(node_id(&new, "a"), Some(a));
+    }
+
+    #[test]
+    fn shortcuts_use_dbusmenu_key_names() {
+        let keystroke = |key: &str, modifiers: Modifiers| Keystroke {
+            modifiers,
+            key: key.to_string(),
+            key_char: None,
+        };
+        let ctrl_shift_p = keystroke(
+            "p",
+            Modifiers {
+                control: true,
+                shift: true,
+                ..Modifiers::default()
+            },
+        );
+        let shortcut = dbusmenu_shortcut(&[
+            ctrl_shift_p.clone(),
+            keystroke(
+                "f5",
+                Modifiers {
+                    alt: true,
+                    platform: true,
+                    ..Modifiers::default()
+                },
+            ),
+        ]);
+        assert_eq!(
+            shortcut,
+            vec![vec!["Control", "Shift", "P"], vec!["Alt", "Super", "F5"],]
+        );
+
+        for (key, keysym) in [
+            ("pageup", "Page_Up"),
+            ("pagedown", "Page_Down"),
+            ("home", "Home"),
+            ("left", "Left"),
+            ("enter", "Return"),
+            ("escape", "Escape"),
+            ("space", "space"),
+            ("f12", "F12"),
+            ("-", "minus"),
+            ("=", "equal"),
+            (",", "comma"),
+            (".", "period"),
+            ("/", "slash"),
+            ("`", "grave"),
+            ("[", "bracketleft"),
+        ] {
+            assert_eq!(dbusmenu_key_name(key), keysym);
+        }
+
+        let menu = DBusMenu::from_tray_menu_items(
+            &[TrayMenuItem::menu("print", "Print", Vec::new()).shortcut([ctrl_shift_p])],
+            MenuIdAllocator::default(),
+        );
+        let value = menu.nodes[&1].properties["shortcut"].to_value();
+        assert_eq!(value.value_signature().to_string(), "aas");
+        assert_eq!(
+            value,
+            zbus::zvariant::Value::from(vec![vec!["Control", "Shift", "P"]])
+        );
+    }
+
+    #[test]
+    fn menu_click_target_records_ancestor_submenus() {
+        let menu = DBusMenu::from_tray_menu_items(
+            &[TrayMenuItem::menu(
+                "devices",
+                "Devices",
+                vec![TrayMenuItem::menu(
+                    "usb",
+                    "USB",
+                    vec![TrayMenuItem::checkbox("mount", "Mount", true)],
+                )],
+            )],
+            MenuIdAllocator::default(),
+        );
+
+        let target = menu
+            .nodes
+            .values()
+            .find_map(|node| node.target.clone().filter(|target| target.id == "mount"))
+            .expect("clickable entry");
+        assert_eq!(target.parents, ["devices", "usb"]);
+        assert_eq!(target.label, "Mount");
+        assert!(target.toggle_type.is_some_and(|toggle| toggle.is_checked()));
+    }
+
+    #[test]
+    fn menu_item_role_exports_disposition() {
+        let menu = DBusMenu::from_tray_menu_items(
+            &[
+                TrayMenuItem::menu("cleanup", "Disk almost full", Vec::new())
+                    .role(TrayMenuItemRole::Warning),
+                TrayMenuItem::menu("quit", "Quit", Vec::new()),
+            ],
+            MenuIdAllocator::default(),
+        );
+
+        assert_eq!(
+            menu.nodes[&1].properties.get("disposition"),
+            Some(&MenuProperty::Disposition("warning"))
+        );
+        assert!(menu.target_for_node(1).is_some());
+        assert!(!menu.nodes[&2].properties.contains_key("disposition"));
+    }
+
+    #[test]
+    fn labeled_separator_renders_header_row() {
+        let menu = DBusMenu::from_tray_menu_items(
+            &TrayMenuItem::section("Devices", [TrayMenuItem::menu("a", "A", Vec::new())]),
+            MenuIdAllocator::default(),
+        );
+
+        let root = &menu.nodes[&0];
+        assert_eq!(root.children.len(), 3);
+        let header = &menu.nodes[&root.children[1]];
+        assert_eq!(
+            header.properties.get("label"),
+            Some(&MenuProperty::Label("Devices".into()))
+        );
+        assert_eq!(
+            header.properties.get("enabled"),
+            Some(&MenuProperty::Enabled(false))
+        );
+    }
+
+    #[test]
+    fn click_tracker_detects_double_clicks() {
+        let policy = TrayClickPolicy::default();
+        let position = Point { x: 0, y: 0 };
+        let start = Instant::now();
+        let mut clicks = ClickTracker::default();
+
+        assert_eq!(
+            clicks.click(position, &policy, start),
+            Some(TrayClickKind::Single)
+        );
+        assert_eq!(
+            clicks.click(position, &policy, start + Duration::from_millis(100)),
+            Some(TrayClickKind::Double)
+        );
+        // A third click starts a new pair.
+        assert_eq!(
+            clicks.click(position, &policy, start + Duration::from_millis(200)),
+            Some(TrayClickKind::Single)
+        );
+        assert_eq!(
+            clicks.click(position, &policy, start + Duration::from_secs(1)),
+            Some(TrayClickKind::Single)
+        );
+    }
+
+    #[test]
+    fn click_tracker_delays_single_click_until_interval_passes() {
+        let policy = TrayClickPolicy::default().delay_single_click(true);
+        let interval = policy.double_click_interval;
+        let position = Point { x: 4, y: 2 };
+        let start = Instant::now();
+        let mut clicks = ClickTracker::default();
+
+        assert_eq!(clicks.click(position, &policy, start), None);
+        assert_eq!(clicks.pending_delay(start), Some(interval));
+        assert_eq!(clicks.take_due(start), None);
+        assert_eq!(clicks.take_due(start + interval), Some(position));
+        assert_eq!(clicks.pending_delay(start + interval), None);
+
+        assert_eq!(clicks.click(position, &policy, start + interval * 3), None);
+        assert_eq!(
+            clicks.click(position, &policy, start + interval * 3 + interval / 2),
+            Some(TrayClickKind::Double)
+        );
+        assert_eq!(clicks.pending_delay(start + interval * 4), None);
+    }
+}
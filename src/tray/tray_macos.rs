@@ -1,30 +1,40 @@
 #![allow(unsafe_op_in_unsafe_fn)]
 
 use crate::tray::{
-    TrayClickAction, TrayClickKind, TrayClickPolicy, TrayEvent, TrayEventCallback,
-    TrayEventCallbackSlot, TrayMenuItem, TrayRuntimeState, TrayState, TrayToggleType,
-    VersionedTrayState,
+    ATTENTION_BLINK_INTERVAL, TrayClickAction, TrayClickKind, TrayClickPolicy, TrayEvent,
+    TrayEventCallback, TrayEventCallbackSlot, TrayMenuIcon, TrayMenuItem, TrayMenuProvider,
+    TrayMenuTarget, TrayRuntimeState, TrayState, TrayStateDiff, TrayToggleType, VersionedTrayState,
+    emit_event,
 };
 use anyhow::{Context as _, Result};
-use gpui::{AsyncApp, MouseButton, Point};
+use gpui::{AsyncApp, Keystroke, MouseButton, Point, Subscription};
 use objc2::rc::{Retained, autoreleasepool};
 use objc2::runtime::{AnyClass, AnyObject, ClassBuilder, NSObject, Sel};
 use objc2::{AnyThread, ClassType, MainThreadMarker, MainThreadOnly, msg_send, sel};
 use objc2_app_kit::{
-    NSApplication, NSCellImagePosition, NSControlStateValueOff, NSControlStateValueOn, NSEvent,
-    NSEventMask, NSEventType, NSImage, NSMenu, NSMenuItem, NSStatusBar, NSStatusItem,
-    NSVariableStatusItemLength,
+    NSApplication, NSCellImagePosition, NSControlStateValueMixed, NSControlStateValueOff,
+    NSControlStateValueOn, NSEvent, NSEventMask, NSEventModifierFlags, NSEventType, NSImage,
+    NSMenu, NSMenuItem, NSStatusBar, NSStatusItem, NSVariableStatusItemLength,
 };
 use objc2_foundation::{NSData, NSSize, NSString};
 use std::{
     cell::RefCell,
     collections::HashMap,
     ffi::c_void,
-    sync::{Arc, Mutex, OnceLock},
+    sync::{
+        Arc, Mutex, OnceLock,
+        atomic::{AtomicI64, AtomicU64, Ordering},
+    },
 };
 
+// Only one tray exists at a time. Handles carry the generation of theirs, so a handle kept
+// past `remove` can't reach a tray set up later.
+static NEXT_TRAY_GENERATION: AtomicU64 = AtomicU64::new(1);
+
 #[derive(Clone, Default)]
-pub struct TrayHandle;
+pub struct TrayHandle {
+    generation: u64,
+}
 
 impl TrayHandle {
     pub fn set_state(&self, state: TrayState) -> Result<()> {
@@ -34,7 +44,8 @@ impl TrayHandle {
                 .map_err(|_| anyhow::anyhow!("tray runtime already borrowed"))?;
             let runtime = runtime_slot
                 .as_mut()
-                .context("tray has not been initialized")?;
+                .filter(|runtime| runtime.generation == self.generation)
+                .context("tray has been removed")?;
             let should_schedule = runtime.state.set_desired_state(state);
             Ok(should_schedule.then(|| runtime.async_app.clone()))
         })?;
@@ -46,8 +57,17 @@ impl TrayHandle {
         Ok(())
     }
 
-    pub fn flush_now(&self, _cx: &mut gpui::App) -> Result<()> {
-        flush_runtime()
+    pub fn flush_now(&self, cx: &mut gpui::App) -> Result<()> {
+        anyhow::ensure!(is_current_tray(self.generation), "tray has been removed");
+        flush_runtime(cx)
+    }
+
+    /// Removes the tray item from the system tray.
+    ///
+    /// Afterwards `set_up_tray` may be called again to install a new tray item. Does nothing
+    /// if this handle's tray is already gone, even if another tray has been set up since.
+    pub fn remove(&self) -> Result<()> {
+        remove_runtime(self.generation)
     }
 }
 
@@ -63,35 +83,126 @@ fn mtm() -> Result<MainThreadMarker> {
 struct Handler {
     async_app: AsyncApp,
     callback: TrayEventCallbackSlot,
-    tag_to_id: Arc<Mutex<HashMap<i64, String>>>,
+    tag_to_target: Arc<Mutex<HashMap<i64, TrayMenuTarget>>>,
+    // Lazy submenus keyed by the address of their NSMenu, populated from `menuNeedsUpdate:`.
+    lazy_menus: Arc<Mutex<HashMap<usize, LazyMenu>>>,
+    next_tag: Arc<AtomicI64>,
+}
+
+struct LazyMenu {
+    // Keeps the address used as key from being reused while the entry exists.
+    _menu: Retained<NSMenu>,
+    provider: TrayMenuProvider,
+    parents: Vec<String>,
 }
 
 impl Handler {
     fn dispatch(&self, event: TrayEvent) {
+        self.run(move |callback, cx| emit_event(callback, event, cx));
+    }
+
+    fn run(&self, f: impl FnOnce(&TrayEventCallbackSlot, &mut gpui::App) + 'static) {
         let async_app = self.async_app.clone();
         let executor = async_app.foreground_executor().clone();
         let callback = self.callback.clone();
         executor
             .spawn(async move {
-                async_app.update(|cx| {
-                    if let Ok(mut slot) = callback.lock()
-                        && let Some(cb) = slot.as_mut()
-                    {
-                        cb(event, cx);
-                    }
-                });
+                async_app.update(|cx| f(&callback, cx));
             })
             .detach();
     }
 
     fn dispatch_tag(&self, tag: i64) {
-        let id = self
-            .tag_to_id
+        let target = self
+            .tag_to_target
             .lock()
             .ok()
             .and_then(|map| map.get(&tag).cloned());
-        if let Some(id) = id {
-            self.dispatch(TrayEvent::MenuClick { id });
+        if let Some(target) = target {
+            self.activate_target(target);
+        }
+    }
+
+    /// Activates the clickable entry with the given ID, as if it had been picked from the menu.
+    /// Returns false if there is no such entry. The first such entry in menu order wins when
+    /// several share the ID.
+    fn dispatch_menu_item(&self, id: &str) -> bool {
+        let target = self.tag_to_target.lock().ok().and_then(|map| {
+            map.iter()
+                .filter(|(_, target)| target.id == id)
+                .min_by_key(|(key, _)| **key)
+                .map(|(_, target)| target.clone())
+        });
+        let Some(target) = target else {
+            return false;
+        };
+        self.activate_target(target);
+        true
+    }
+
+    fn activate_target(&self, mut target: TrayMenuTarget) {
+        // NSEvent timestamps are seconds since system startup. Like X11 and Win32 times, the
+        // milliseconds wrap around after about 49.7 days instead of saturating.
+        target.timestamp = MainThreadMarker::new()
+            .and_then(|mtm| NSApplication::sharedApplication(mtm).currentEvent())
+            .map(|event| (event.timestamp() * 1000.0) as u64 as u32);
+        refresh_target(&mut target);
+        target.checked = toggle_menu_item(&target.parents, &target.id);
+        self.run(move |callback, cx| target.activate(callback, cx));
+    }
+
+    /// Rebuilds the items of a lazy submenu from its provider. Does nothing for other menus.
+    unsafe fn populate_lazy_menu(&self, menu: &NSMenu, target: &AnyObject) -> Result<()> {
+        let key = menu as *const NSMenu as usize;
+        let lazy_menu = self.lazy_menus.lock().ok().and_then(|menus| {
+            menus
+                .get(&key)
+                .map(|lazy_menu| (lazy_menu.provider.clone(), lazy_menu.parents.clone()))
+        });
+        let Some((provider, parents)) = lazy_menu else {
+            return Ok(());
+        };
+
+        let provider = refresh_provider(provider);
+        let items = self.async_app.update(|cx| provider.populate(cx));
+        self.forget_menu_items(menu);
+        menu.removeAllItems();
+
+        let mtm = mtm()?;
+        let mut next_tag = self.next_tag.load(Ordering::Relaxed);
+        for item in &items {
+            add_tray_menu_item(menu, item, &parents, self, target, mtm, &mut next_tag)?;
+        }
+        self.next_tag.store(next_tag, Ordering::Relaxed);
+        Ok(())
+    }
+
+    // Drops the targets and lazy submenus registered for the items of `menu`.
+    fn forget_menu_items(&self, menu: &NSMenu) {
+        for index in 0..menu.numberOfItems() {
+            let Some(item) = menu.itemAtIndex(index) else {
+                continue;
+            };
+            if let Ok(mut map) = self.tag_to_target.lock() {
+                map.remove(&(item.tag() as i64));
+            }
+            if let Some(submenu) = item.submenu() {
+                if let Ok(mut menus) = self.lazy_menus.lock() {
+                    menus.remove(&(Retained::as_ptr(&submenu) as usize));
+                }
+                self.forget_menu_items(&submenu);
+            }
+        }
+    }
+
+    fn dispatch_hover_tag(&self, tag: i64) {
+        let id = self
+            .tag_to_target
+            .lock()
+            .ok()
+            .and_then(|map| map.get(&tag).map(|target| target.id.clone()));
+        if let Some(id) = id.filter(|id| !id.is_empty()) {
+            self.dispatch(TrayEvent::MenuItemHovered { id });
         }
     }
 }
@@ -114,13 +225,20 @@ struct TrayPlatform {
     target: Retained<AnyObject>,
     handler: Handler,
     click_policy: TrayClickPolicy,
+    // Decoded once per change so blink phases only swap images.
+    icon_image: Option<Retained<NSImage>>,
+    attention_image: Option<Retained<NSImage>>,
 }
 
 struct TrayRuntime {
+    generation: u64,
     async_app: AsyncApp,
     state: TrayRuntimeState,
     platform: Option<Box<TrayPlatform>>,
     interaction_active: bool,
+    blink_timer_running: bool,
+    // Unregisters the quit hook when the tray is removed.
+    _quit_subscription: Subscription,
 }
 
 thread_local! {
@@ -174,6 +292,56 @@ fn target_class() -> Result<&'static AnyClass> {
             }
         }
 
+        // NSMenuDelegate `menu:willHighlightItem:`; `item` is nil when the highlight leaves
+        // the menu.
+        extern "C" fn on_menu_highlight(
+            this: *mut NSObject,
+            _cmd: Sel,
+            _menu: *mut AnyObject,
+            item: *mut AnyObject,
+        ) {
+            unsafe {
+                let Some(this) = this.as_ref() else {
+                    return;
+                };
+                if item.is_null() {
+                    return;
+                }
+                let cls = AnyClass::get(c"GpuiTrayTarget").unwrap();
+                let ivar = cls.instance_variable(c"rust_state").unwrap();
+                let state_ptr = *ivar.load::<*mut c_void>(this);
+                if state_ptr.is_null() {
+                    return;
+                }
+
+                let tag: i64 = msg_send![item, tag];
+                let state = &*(state_ptr as *const TargetState);
+                state.handler.dispatch_hover_tag(tag);
+            }
+        }
+
+        // NSMenuDelegate `menuNeedsUpdate:`, sent right before a menu opens.
+        extern "C" fn on_menu_needs_update(this: *mut NSObject, _cmd: Sel, menu: *mut AnyObject) {
+            unsafe {
+                let Some(this) = this.as_ref() else {
+                    return;
+                };
+                let Some(menu) = menu.cast::<NSMenu>().as_ref() else {
+                    return;
+                };
+                let cls = AnyClass::get(c"GpuiTrayTarget").unwrap();
+                let ivar = cls.instance_variable(c"rust_state").unwrap();
+                let state_ptr = *ivar.load::<*mut c_void>(this);
+                if state_ptr.is_null() {
+                    return;
+                }
+
+                let state = &*(state_ptr as *const TargetState);
+                let target = &*(this as *const NSObject).cast::<AnyObject>();
+                let _ = state.handler.populate_lazy_menu(menu, target);
+            }
+        }
+
         extern "C" fn on_status_item_click(
             _this: *mut NSObject,
             _cmd: Sel,
@@ -207,8 +375,18 @@ fn target_class() -> Result<&'static AnyClass> {
             let on_menu_item_fn: extern "C" fn(*mut NSObject, Sel, *mut AnyObject) = on_menu_item;
             let on_status_item_click_fn: extern "C" fn(*mut NSObject, Sel, *mut AnyObject) =
                 on_status_item_click;
+            let on_menu_highlight_fn: extern "C" fn(
+                *mut NSObject,
+                Sel,
+                *mut AnyObject,
+                *mut AnyObject,
+            ) = on_menu_highlight;
+            let on_menu_needs_update_fn: extern "C" fn(*mut NSObject, Sel, *mut AnyObject) =
+                on_menu_needs_update;
             let dealloc_fn: extern "C" fn(*mut NSObject, Sel) = dealloc;
             builder.add_method::<NSObject, _>(sel!(onMenuItem:), on_menu_item_fn);
+            builder.add_method::<NSObject, _>(sel!(menu:willHighlightItem:), on_menu_highlight_fn);
+            builder.add_method::<NSObject, _>(sel!(menuNeedsUpdate:), on_menu_needs_update_fn);
             builder.add_method::<NSObject, _>(sel!(onStatusItemClick:), on_status_item_click_fn);
             builder.add_method::<NSObject, _>(sel!(dealloc), dealloc_fn);
         }
@@ -231,11 +409,13 @@ pub fn set_up_tray(
         let menu = NSMenu::new(mtm);
 
         let callback = Arc::new(Mutex::new(Some(on_event)));
-        let tag_to_id = Arc::new(Mutex::new(HashMap::new()));
+        let tag_to_target = Arc::new(Mutex::new(HashMap::new()));
         let handler = Handler {
             async_app: async_app.clone(),
             callback,
-            tag_to_id,
+            tag_to_target,
+            lazy_menus: Arc::new(Mutex::new(HashMap::new())),
+            next_tag: Arc::new(AtomicI64::new(1)),
         };
 
         let state = Box::new(TargetState {
@@ -247,6 +427,13 @@ pub fn set_up_tray(
         let target: Retained<AnyObject> = msg_send![target_class, new];
         let ivar = target_class.instance_variable(c"rust_state").unwrap();
         *ivar.load_ptr::<*mut c_void>(&target) = state_ptr;
+        let _: () = msg_send![&*menu, setDelegate: &*target];
+
+        let generation = NEXT_TRAY_GENERATION.fetch_add(1, Ordering::Relaxed);
+        let quit_subscription = cx.on_app_quit(move |_| {
+            let _ = remove_runtime(generation);
+            async {}
+        });
 
         TRAY_RUNTIME.with(|runtime_cell| {
             let mut runtime_slot = runtime_cell
@@ -257,6 +444,7 @@ pub fn set_up_tray(
             }
 
             *runtime_slot = Some(TrayRuntime {
+                generation,
                 async_app: async_app.clone(),
                 state: TrayRuntimeState::new(initial),
                 platform: Some(Box::new(TrayPlatform {
@@ -266,24 +454,133 @@ pub fn set_up_tray(
                     target,
                     handler,
                     click_policy: TrayClickPolicy::default(),
+                    icon_image: None,
+                    attention_image: None,
                 })),
                 interaction_active: false,
+                blink_timer_running: false,
+                _quit_subscription: quit_subscription,
             });
 
             Ok(())
         })?;
 
-        let handle = TrayHandle;
+        let handle = TrayHandle { generation };
         handle.flush_now(cx)?;
         Ok(handle)
     })
 }
 
-fn schedule_flush(async_app: AsyncApp) {
+fn is_current_tray(generation: u64) -> bool {
+    TRAY_RUNTIME.with(|runtime_cell| {
+        runtime_cell.try_borrow().is_ok_and(|runtime_slot| {
+            runtime_slot
+                .as_ref()
+                .is_some_and(|runtime| runtime.generation == generation)
+        })
+    })
+}
+
+fn remove_runtime(generation: u64) -> Result<()> {
+    let runtime = TRAY_RUNTIME.with(|runtime_cell| -> Result<Option<TrayRuntime>> {
+        let mut runtime_slot = runtime_cell
+            .try_borrow_mut()
+            .map_err(|_| anyhow::anyhow!("tray runtime already borrowed"))?;
+        let current = runtime_slot
+            .as_ref()
+            .is_some_and(|runtime| runtime.generation == generation);
+        Ok(if current { runtime_slot.take() } else { None })
+    })?;
+
+    // Dropping the platform removes the status item; do it outside the borrow since AppKit may
+    // call back into the tray while tearing it down.
+    drop(runtime);
+    Ok(())
+}
+
+/// Picks up the closures of a state that was set without re-rendering the menu, see
+/// [`TrayRuntimeState::refresh_target`].
+fn refresh_target(target: &mut TrayMenuTarget) {
+    TRAY_RUNTIME.with(|runtime_cell| {
+        if let Ok(runtime_slot) = runtime_cell.try_borrow()
+            && let Some(runtime) = runtime_slot.as_ref()
+        {
+            runtime.state.refresh_target(target);
+        }
+    });
+}
+
+/// Like [`refresh_target`], for the provider of a lazy submenu.
+fn refresh_provider(provider: TrayMenuProvider) -> TrayMenuProvider {
+    TRAY_RUNTIME.with(|runtime_cell| match runtime_cell.try_borrow() {
+        Ok(runtime_slot) => match runtime_slot.as_ref() {
+            Some(runtime) => runtime.state.refresh_provider(provider),
+            None => provider,
+        },
+        Err(_) => provider,
+    })
+}
+
+/// Applies [`TrayState::auto_toggle`] for a clicked entry and schedules the resulting flush.
+fn toggle_menu_item(parents: &[String], id: &str) -> Option<bool> {
+    let (checked, async_app) = TRAY_RUNTIME.with(|runtime_cell| {
+        let mut runtime_slot = runtime_cell.try_borrow_mut().ok()?;
+        let runtime = runtime_slot.as_mut()?;
+        let checked = runtime.state.toggle_menu_item(parents, id)?;
+        Some((checked, runtime.async_app.clone()))
+    })?;
+    schedule_flush(async_app);
+    Some(checked)
+}
+
+/// Starts the timer driving [`TrayState::attention_blink`] unless it is already running or
+/// the state doesn't blink.
+fn start_blink_timer(runtime: &mut TrayRuntime) {
+    if runtime.blink_timer_running || !runtime.state.is_blinking() {
+        return;
+    }
+    runtime.blink_timer_running = true;
+
+    let async_app = runtime.async_app.clone();
     let executor = async_app.foreground_executor().clone();
     executor
         .spawn(async move {
-            let _ = flush_runtime();
+            loop {
+                async_app
+                    .background_executor()
+                    .timer(ATTENTION_BLINK_INTERVAL)
+                    .await;
+                let blinking = TRAY_RUNTIME.with(|runtime_cell| {
+                    // Busy with a flush or click; try again on the next tick.
+                    let Ok(mut runtime_slot) = runtime_cell.try_borrow_mut() else {
+                        return true;
+                    };
+                    let Some(runtime) = runtime_slot.as_mut() else {
+                        return false;
+                    };
+                    let blinking = runtime.state.advance_blink();
+                    runtime.blink_timer_running = blinking;
+                    blinking
+                });
+                if !blinking {
+                    break;
+                }
+                schedule_flush(async_app.clone());
+            }
+        })
+        .detach();
+}
+
+fn schedule_flush(async_app: AsyncApp) {
+    let executor = async_app.foreground_executor().clone();
+    executor
+        .spawn({
+            let async_app = async_app.clone();
+            async move {
+                async_app.update(|cx| {
+                    let _ = flush_runtime(cx);
+                });
+            }
         })
         .detach();
 }
@@ -297,6 +594,8 @@ fn handle_status_item_click() -> Result<()> {
             .as_mut()
             .context("tray has not been initialized")?;
         runtime.interaction_active = true;
+        // The flush restoring the regular icon is scheduled once the click is handled.
+        runtime.state.stop_blink();
         runtime
             .platform
             .take()
@@ -327,7 +626,7 @@ fn handle_status_item_click() -> Result<()> {
     click_result
 }
 
-fn flush_runtime() -> Result<()> {
+fn flush_runtime(cx: &mut gpui::App) -> Result<()> {
     with_pool(|| {
         loop {
             let step = TRAY_RUNTIME.with(
@@ -359,8 +658,19 @@ fn flush_runtime() -> Result<()> {
             let Some((mut platform, versioned_state)) = step else {
                 return Ok(());
             };
-
-            let apply_result = platform.apply(&versioned_state.state);
+            // The diff and the applied state stay those of the state the app set; availability
+            // only shapes what is shown.
+            let diff = TRAY_RUNTIME
+                .with(|runtime_cell| {
+                    let runtime_slot = runtime_cell.try_borrow().ok()?;
+                    let runtime = runtime_slot.as_ref()?;
+                    Some(runtime.state.flush_diff(&versioned_state))
+                })
+                .unwrap_or_else(TrayStateDiff::all);
+            let mut shown_state = versioned_state.state.clone();
+            shown_state.resolve_action_availability(cx);
+
+            let apply_result = platform.apply(&shown_state, versioned_state.blink_on, &diff);
 
             let should_continue = TRAY_RUNTIME.with(|runtime_cell| -> Result<bool> {
                 let mut runtime_slot = runtime_cell
@@ -372,6 +682,7 @@ fn flush_runtime() -> Result<()> {
                 runtime.platform = Some(platform);
 
                 if apply_result.is_ok() {
+                    start_blink_timer(runtime);
                     Ok(runtime.state.finish_flush(versioned_state))
                 } else {
                     runtime.state.abort_flush();
@@ -392,49 +703,75 @@ impl TrayPlatform {
     fn status_item_click_context(&self) -> StatusItemClickContext {
         StatusItemClickContext {
             handler: self.handler.clone(),
-            click_policy: self.click_policy,
+            click_policy: self.click_policy.clone(),
             status_item: self.status_item.clone(),
             menu: self.menu.clone(),
         }
     }
 
-    fn apply(&mut self, state: &TrayState) -> Result<()> {
+    fn apply(&mut self, state: &TrayState, blink_on: bool, diff: &TrayStateDiff) -> Result<()> {
+        self.click_policy = state.click_policy.clone();
+
+        let created = state.visible && self.status_item.is_none();
         self.set_visible(state.visible)?;
         if !state.visible {
             return Ok(());
         }
 
-        self.rebuild_menu(&state.submenus)?;
+        // A new status item starts out blank, and changes made while hidden were skipped.
+        let diff = if created { TrayStateDiff::all() } else { *diff };
+
+        if diff.menu {
+            self.rebuild_menu(&state.submenus)?;
+        }
 
         let status_item = self.status_item.as_ref().context("status item is nil")?;
         let button = status_item
             .button(self.mtm)
             .context("status item button is nil")?;
 
-        unsafe { button.setTarget(Some(&self.target)) };
-        unsafe { button.setAction(Some(sel!(onStatusItemClick:))) };
-        button.sendActionOn(
-            NSEventMask::LeftMouseUp | NSEventMask::RightMouseUp | NSEventMask::OtherMouseUp,
-        );
-
-        let tooltip = NSString::from_str(state.tooltip.as_str());
-        button.setToolTip(Some(&tooltip));
-
-        let title = NSString::from_str(state.title.as_str());
-        button.setTitle(&title);
-
-        let nsimage = state.icon.as_deref().map(nsimage_from_image).transpose()?;
-        if let Some(nsimage) = nsimage {
-            let new_size = NSSize::new(18., 18.);
-            button.setImage(Some(&nsimage));
-            nsimage.setSize(new_size);
-            button.setImagePosition(NSCellImagePosition::ImageLeft);
-            nsimage.setTemplate(true);
-        } else {
-            button.setImage(None);
+        if created {
+            unsafe { button.setTarget(Some(&self.target)) };
+            unsafe { button.setAction(Some(sel!(onStatusItemClick:))) };
+            button.sendActionOn(
+                NSEventMask::LeftMouseUp | NSEventMask::RightMouseUp | NSEventMask::OtherMouseUp,
+            );
+        }
+
+        if diff.tooltip {
+            let tooltip = NSString::from_str(state.tooltip.as_str());
+            button.setToolTip(Some(&tooltip));
+        }
+
+        if diff.title {
+            let title = NSString::from_str(state.title.as_str());
+            button.setTitle(&title);
+        }
+
+        if diff.icon {
+            self.icon_image = state.icon.as_deref().map(status_item_image).transpose()?;
+        }
+        if diff.attention {
+            self.attention_image = state
+                .attention_icon
+                .as_deref()
+                .map(status_item_image)
+                .transpose()?;
+        }
+        if diff.icon || diff.attention || diff.blink {
+            let nsimage = if blink_on {
+                self.attention_image.as_ref().or(self.icon_image.as_ref())
+            } else {
+                self.icon_image.as_ref()
+            };
+            if let Some(nsimage) = nsimage {
+                button.setImage(Some(nsimage));
+                button.setImagePosition(NSCellImagePosition::ImageLeft);
+            } else {
+                button.setImage(None);
+            }
         }
 
-        self.click_policy = state.click_policy;
         Ok(())
     }
 
@@ -467,21 +804,27 @@ impl TrayPlatform {
         with_pool(|| unsafe {
             self.menu.removeAllItems();
 
-            if let Ok(mut map) = self.handler.tag_to_id.lock() {
+            if let Ok(mut map) = self.handler.tag_to_target.lock() {
                 map.clear();
             }
+            if let Ok(mut menus) = self.handler.lazy_menus.lock() {
+                menus.clear();
+            }
 
             let mut next_tag: i64 = 1;
             for item in items {
                 add_tray_menu_item(
                     &self.menu,
                     item,
+                    &[],
                     &self.handler,
                     &self.target,
                     self.mtm,
                     &mut next_tag,
                 )?;
             }
+            // Lazy submenus continue numbering from here when they are populated.
+            self.handler.next_tag.store(next_tag, Ordering::Relaxed);
 
             Ok(())
         })
@@ -516,6 +859,18 @@ impl StatusItemClickContext {
                 MouseButton::Left,
                 TrayClickKind::Single,
             ),
+            NSEventType::OtherMouseUp => {
+                if let Some(id) = self.click_policy.middle_menu_item.as_deref()
+                    && self.handler.dispatch_menu_item(id)
+                {
+                    return Ok(());
+                }
+                (
+                    self.click_policy.middle,
+                    MouseButton::Middle,
+                    TrayClickKind::Single,
+                )
+            }
             _ => return Ok(()),
         };
 
@@ -529,8 +884,11 @@ impl StatusItemClickContext {
             }
             TrayClickAction::OpenMenu => {
                 if let Some(status_item) = self.status_item.as_ref() {
+                    // popUpStatusItemMenu runs the menu's tracking loop until it closes.
+                    self.handler.dispatch(TrayEvent::MenuOpened { id: None });
                     #[allow(deprecated)]
                     status_item.popUpStatusItemMenu(&self.menu);
+                    self.handler.dispatch(TrayEvent::MenuClosed { id: None });
                 }
             }
             TrayClickAction::Ignore => {}
@@ -548,9 +906,64 @@ fn nsimage_from_image(image: &gpui::Image) -> Result<Retained<NSImage>> {
         .context("failed to create NSImage from gpui::Image bytes")
 }
 
+fn status_item_image(image: &gpui::Image) -> Result<Retained<NSImage>> {
+    let nsimage = nsimage_from_image(image)?;
+    nsimage.setSize(NSSize::new(18., 18.));
+    nsimage.setTemplate(true);
+    Ok(nsimage)
+}
+
+fn set_menu_item_icon(menu_item: &NSMenuItem, icon: Option<&TrayMenuIcon>) {
+    // Themed icon names have no AppKit equivalent.
+    let Some(TrayMenuIcon::Image(image)) = icon else {
+        return;
+    };
+    // An icon that fails to decode shouldn't take the whole menu down.
+    if let Ok(nsimage) = nsimage_from_image(image) {
+        nsimage.setSize(NSSize::new(16., 16.));
+        menu_item.setImage(Some(&nsimage));
+    }
+}
+
+fn set_menu_item_shortcut(menu_item: &NSMenuItem, shortcut: &[Keystroke]) {
+    // AppKit can only show a single keystroke, so chords show their first step.
+    let Some(keystroke) = shortcut.first() else {
+        return;
+    };
+    let key = match keystroke.key.as_str() {
+        "enter" => "\r",
+        "escape" => "\u{1b}",
+        "tab" => "\t",
+        "backspace" => "\u{8}",
+        "delete" => "\u{7f}",
+        "space" => " ",
+        key if key.chars().count() == 1 => key,
+        _ => return,
+    };
+
+    let modifiers = &keystroke.modifiers;
+    let mut mask = NSEventModifierFlags(0);
+    if modifiers.control {
+        mask = mask | NSEventModifierFlags::Control;
+    }
+    if modifiers.alt {
+        mask = mask | NSEventModifierFlags::Option;
+    }
+    if modifiers.shift {
+        mask = mask | NSEventModifierFlags::Shift;
+    }
+    if modifiers.platform {
+        mask = mask | NSEventModifierFlags::Command;
+    }
+
+    menu_item.setKeyEquivalent(&NSString::from_str(key));
+    menu_item.setKeyEquivalentModifierMask(mask);
+}
+
 unsafe fn add_tray_menu_item(
     menu: &NSMenu,
     item: &TrayMenuItem,
+    parents: &[String],
     handler: &Handler,
     target: &AnyObject,
     mtm: MainThreadMarker,
@@ -563,21 +976,31 @@ unsafe fn add_tray_menu_item(
             }
             let separator = NSMenuItem::separatorItem(mtm);
             menu.addItem(&separator);
+            if let Some(header) = item.section_header() {
+                add_tray_menu_item(menu, &header, parents, handler, target, mtm, next_tag)?;
+            }
         }
         TrayMenuItem::Submenu {
-            id: _,
+            id,
             label,
             enabled,
             visible,
             role: _,
             toggle_type,
+            icon,
+            shortcut,
+            mnemonic: _,
+            payload: _,
+            on_click: _,
+            action: _,
+            provider,
             children,
         } => {
             if !*visible {
                 return Ok(());
             }
 
-            if children.is_empty() {
+            if children.is_empty() && provider.is_none() {
                 let title = NSString::from_str(label.as_str());
                 let key_equiv = NSString::from_str("");
                 let menu_item = NSMenuItem::initWithTitle_action_keyEquivalent(
@@ -586,34 +1009,54 @@ unsafe fn add_tray_menu_item(
                     Some(sel!(onMenuItem:)),
                     &key_equiv,
                 );
-                if let Some(user_id) = item.menu_event_id() {
+                if let Some(menu_target) = item.menu_target(parents) {
                     let tag = *next_tag;
                     *next_tag += 1;
 
-                    if let Ok(mut map) = handler.tag_to_id.lock() {
-                        map.insert(tag, user_id.to_string());
+                    if let Ok(mut map) = handler.tag_to_target.lock() {
+                        map.insert(tag, menu_target);
                     }
 
                     unsafe { menu_item.setTarget(Some(target)) };
                     menu_item.setTag(tag as _);
                 }
 
-                let checked = match toggle_type {
-                    Some(TrayToggleType::Checkbox(checked)) => *checked,
-                    Some(TrayToggleType::Radio(checked)) => *checked,
-                    None => false,
-                };
-                menu_item.setState(if checked {
-                    NSControlStateValueOn
-                } else {
-                    NSControlStateValueOff
+                menu_item.setState(match toggle_type {
+                    Some(TrayToggleType::Indeterminate) => NSControlStateValueMixed,
+                    Some(toggle) if toggle.is_checked() => NSControlStateValueOn,
+                    _ => NSControlStateValueOff,
                 });
                 menu_item.setEnabled(*enabled);
+                set_menu_item_icon(&menu_item, icon.as_ref());
+                set_menu_item_shortcut(&menu_item, shortcut);
                 menu.addItem(&menu_item);
             } else {
                 let submenu = NSMenu::new(mtm);
+                let _: () = msg_send![&*submenu, setDelegate: target];
+                let mut child_parents = parents.to_vec();
+                child_parents.push(id.clone().unwrap_or_default());
+                if let Some(provider) = provider
+                    && let Ok(mut menus) = handler.lazy_menus.lock()
+                {
+                    menus.insert(
+                        Retained::as_ptr(&submenu) as usize,
+                        LazyMenu {
+                            _menu: submenu.clone(),
+                            provider: provider.clone(),
+                            parents: child_parents.clone(),
+                        },
+                    );
+                }
                 for child in children {
-                    add_tray_menu_item(&submenu, child, handler, target, mtm, next_tag)?;
+                    add_tray_menu_item(
+                        &submenu,
+                        child,
+                        &child_parents,
+                        handler,
+                        target,
+                        mtm,
+                        next_tag,
+                    )?;
                 }
 
                 let title = NSString::from_str(label.as_str());
@@ -621,6 +1064,7 @@ unsafe fn add_tray_menu_item(
                 menu_item.setTitle(&title);
                 menu_item.setEnabled(*enabled);
                 menu_item.setSubmenu(Some(&submenu));
+                set_menu_item_icon(&menu_item, icon.as_ref());
                 menu.addItem(&menu_item);
             }
         }
@@ -1,12 +1,13 @@
 #![allow(unsafe_op_in_unsafe_fn)]
 
 use crate::tray::{
-    TrayClickAction, TrayClickKind, TrayClickPolicy, TrayEvent, TrayEventCallback,
-    TrayEventCallbackSlot, TrayMenuItem, TrayRuntimeState, TrayState, TrayToggleType,
-    VersionedTrayState,
+    ATTENTION_BLINK_INTERVAL, TrayClickAction, TrayClickKind, TrayClickPolicy, TrayEvent,
+    TrayEventCallback, TrayEventCallbackSlot, TrayMenuIcon, TrayMenuItem, TrayMenuTarget,
+    TrayRuntimeState, TrayState, TrayStateDiff, TrayToggleType, VersionedTrayState, emit_event,
+    mnemonic_label,
 };
 use anyhow::{Context as _, Result};
-use gpui::{AsyncApp, MouseButton, Point};
+use gpui::{AsyncApp, Keystroke, MouseButton, Point, Subscription};
 use std::{
     cell::RefCell,
     collections::HashMap,
@@ -14,13 +15,16 @@ use std::{
     mem,
     os::windows::ffi::OsStrExt as _,
     ptr,
-    sync::{Arc, Mutex, OnceLock},
+    sync::{
+        Arc, Mutex, OnceLock,
+        atomic::{AtomicU64, Ordering},
+    },
 };
 use windows_sys::Win32::{
     Foundation::{HMODULE, HWND, LPARAM, LRESULT, POINT as WIN_POINT, WPARAM},
     Graphics::Gdi::{
         BI_RGB, BITMAPINFO, BITMAPINFOHEADER, CreateBitmap, CreateDIBSection, DIB_RGB_COLORS,
-        DeleteObject,
+        DeleteObject, HBITMAP,
     },
     System::LibraryLoader::GetModuleHandleW,
     UI::{
@@ -31,19 +35,26 @@ use windows_sys::Win32::{
         WindowsAndMessaging::{
             AppendMenuW, CREATESTRUCTW, CW_USEDEFAULT, CreateIconIndirect, CreatePopupMenu,
             CreateWindowExW, DefWindowProcW, DestroyIcon, DestroyMenu, DestroyWindow, GetCursorPos,
-            HICON, HMENU, ICONINFO, IDC_ARROW, IDI_APPLICATION, LoadCursorW, LoadIconW, MF_CHECKED,
-            MF_DISABLED, MF_POPUP, MF_SEPARATOR, MF_STRING, MF_UNCHECKED, PostMessageW,
-            PostQuitMessage, RegisterClassW, SetForegroundWindow, TPM_BOTTOMALIGN, TPM_LEFTALIGN,
-            TPM_RETURNCMD, TPM_RIGHTBUTTON, TrackPopupMenu, WM_COMMAND, WM_CONTEXTMENU, WM_CREATE,
-            WM_DESTROY, WM_LBUTTONDBLCLK, WM_LBUTTONUP, WM_NULL, WM_RBUTTONUP, WM_USER, WNDCLASSW,
-            WS_OVERLAPPEDWINDOW,
+            GetMenuItemCount, GetMessageTime, HICON, HMENU, ICONINFO, IDC_ARROW, IDI_APPLICATION,
+            LoadCursorW, LoadIconW, MENUITEMINFOW, MF_CHECKED, MF_DISABLED, MF_POPUP, MF_SEPARATOR,
+            MF_STRING, MF_UNCHECKED, MIIM_BITMAP, PostMessageW, RegisterClassW,
+            SetForegroundWindow, SetMenuItemInfoW, TPM_BOTTOMALIGN, TPM_LEFTALIGN, TPM_RETURNCMD,
+            TPM_RIGHTBUTTON, TrackPopupMenu, WM_COMMAND, WM_CONTEXTMENU, WM_CREATE, WM_DESTROY,
+            WM_LBUTTONDBLCLK, WM_LBUTTONUP, WM_MBUTTONUP, WM_MENUSELECT, WM_NULL, WM_RBUTTONUP,
+            WM_USER, WNDCLASSW, WS_OVERLAPPEDWINDOW,
         },
     },
 };
 use windows_sys::core::BOOL;
 
+// Only one tray exists at a time. Handles carry the generation of theirs, so a handle kept
+// past `remove` can't reach a tray set up later.
+static NEXT_TRAY_GENERATION: AtomicU64 = AtomicU64::new(1);
+
 #[derive(Clone, Default)]
-pub struct TrayHandle;
+pub struct TrayHandle {
+    generation: u64,
+}
 
 impl TrayHandle {
     pub fn set_state(&self, state: TrayState) -> Result<()> {
@@ -53,7 +64,8 @@ impl TrayHandle {
                 .map_err(|_| anyhow::anyhow!("tray runtime already borrowed"))?;
             let runtime = runtime_slot
                 .as_mut()
-                .context("tray has not been initialized")?;
+                .filter(|runtime| runtime.generation == self.generation)
+                .context("tray has been removed")?;
             let should_schedule = runtime.state.set_desired_state(state);
             Ok(should_schedule.then(|| runtime.async_app.clone()))
         })?;
@@ -65,8 +77,17 @@ impl TrayHandle {
         Ok(())
     }
 
-    pub fn flush_now(&self, _cx: &mut gpui::App) -> Result<()> {
-        flush_runtime()
+    pub fn flush_now(&self, cx: &mut gpui::App) -> Result<()> {
+        anyhow::ensure!(is_current_tray(self.generation), "tray has been removed");
+        flush_runtime(cx, false)
+    }
+
+    /// Removes the tray item from the system tray.
+    ///
+    /// Afterwards `set_up_tray` may be called again to install a new tray item. Does nothing
+    /// if this handle's tray is already gone, even if another tray has been set up since.
+    pub fn remove(&self) -> Result<()> {
+        remove_runtime(self.generation)
     }
 }
 
@@ -76,40 +97,77 @@ const WM_TRAY_OPEN_MENU: u32 = WM_USER + 2;
 const TRAY_CLICK_LEFT_SINGLE: usize = 0;
 const TRAY_CLICK_RIGHT_SINGLE: usize = 1;
 const TRAY_CLICK_LEFT_DOUBLE: usize = 2;
+const TRAY_CLICK_MIDDLE_SINGLE: usize = 3;
+// Shows the menu without consulting the click policy, once its lazy submenus are populated.
+const TRAY_SHOW_MENU: usize = 4;
 
 #[derive(Clone)]
 struct Handler {
     async_app: AsyncApp,
     callback: TrayEventCallbackSlot,
-    id_to_menu_id: Arc<Mutex<HashMap<u16, String>>>,
+    id_to_target: Arc<Mutex<HashMap<u16, TrayMenuTarget>>>,
 }
 
 impl Handler {
     fn dispatch(&self, event: TrayEvent) {
+        self.run(move |callback, cx| emit_event(callback, event, cx));
+    }
+
+    fn run(&self, f: impl FnOnce(&TrayEventCallbackSlot, &mut gpui::App) + 'static) {
         let async_app = self.async_app.clone();
         let executor = async_app.foreground_executor().clone();
         let callback = self.callback.clone();
         executor
             .spawn(async move {
-                async_app.update(|cx| {
-                    if let Ok(mut slot) = callback.lock()
-                        && let Some(cb) = slot.as_mut()
-                    {
-                        cb(event, cx);
-                    }
-                });
+                async_app.update(|cx| f(&callback, cx));
             })
             .detach();
     }
 
     fn dispatch_command(&self, cmd: u16) {
-        let id = self
-            .id_to_menu_id
+        let target = self
+            .id_to_target
             .lock()
             .ok()
             .and_then(|map| map.get(&cmd).cloned());
-        if let Some(id) = id {
-            self.dispatch(TrayEvent::MenuClick { id });
+        if let Some(target) = target {
+            self.activate_target(target);
+        }
+    }
+
+    /// Activates the clickable entry with the given ID, as if it had been picked from the menu.
+    /// Returns false if there is no such entry. The first such entry in menu order wins when
+    /// several share the ID.
+    fn dispatch_menu_item(&self, id: &str) -> bool {
+        let target = self.id_to_target.lock().ok().and_then(|map| {
+            map.iter()
+                .filter(|(_, target)| target.id == id)
+                .min_by_key(|(key, _)| **key)
+                .map(|(_, target)| target.clone())
+        });
+        let Some(target) = target else {
+            return false;
+        };
+        self.activate_target(target);
+        true
+    }
+
+    fn activate_target(&self, mut target: TrayMenuTarget) {
+        // Called while handling a posted message, so this is the time it was posted.
+        target.timestamp = Some(unsafe { GetMessageTime() } as u32);
+        refresh_target(&mut target);
+        target.checked = toggle_menu_item(&target.parents, &target.id);
+        self.run(move |callback, cx| target.activate(callback, cx));
+    }
+
+    fn dispatch_hover(&self, cmd: u16) {
+        let id = self
+            .id_to_target
+            .lock()
+            .ok()
+            .and_then(|map| map.get(&cmd).map(|target| target.id.clone()));
+        if let Some(id) = id.filter(|id| !id.is_empty()) {
+            self.dispatch(TrayEvent::MenuItemHovered { id });
         }
     }
 }
@@ -118,17 +176,26 @@ struct TrayPlatform {
     handler: Handler,
     hwnd: HWND,
     menu: HMENU,
+    menu_bitmaps: Vec<HBITMAP>,
+    has_lazy_submenus: bool,
     click_policy: TrayClickPolicy,
     icon_added: bool,
     hicon: HICON,
     hicon_owned: bool,
+    // Decoded along with the icon so blink phases only swap handles.
+    attention_hicon: HICON,
+    blink_on: bool,
 }
 
 struct TrayRuntime {
+    generation: u64,
     async_app: AsyncApp,
     state: TrayRuntimeState,
     platform: Option<Box<TrayPlatform>>,
     interaction_active: bool,
+    blink_timer_running: bool,
+    // Unregisters the quit hook when the tray is removed.
+    _quit_subscription: Subscription,
 }
 
 impl Drop for TrayPlatform {
@@ -140,12 +207,17 @@ impl Drop for TrayPlatform {
                 self.hicon = ptr::null_mut();
                 self.hicon_owned = false;
             }
+            if self.attention_hicon != ptr::null_mut() {
+                DestroyIcon(self.attention_hicon);
+                self.attention_hicon = ptr::null_mut();
+            }
             if self.hwnd != ptr::null_mut() {
                 DestroyWindow(self.hwnd);
             }
             if self.menu != ptr::null_mut() {
                 DestroyMenu(self.menu);
             }
+            self.destroy_menu_bitmaps();
         }
     }
 }
@@ -158,6 +230,72 @@ fn to_wide_null(text: impl AsRef<OsStr>) -> Vec<u16> {
     text.as_ref().encode_wide().chain(Some(0)).collect()
 }
 
+fn menu_item_text(label: &str, shortcut: &[Keystroke]) -> String {
+    if shortcut.is_empty() {
+        return label.to_string();
+    }
+
+    // Text after a tab is drawn right-aligned in the menu's accelerator column.
+    let shortcut = shortcut
+        .iter()
+        .map(keystroke_text)
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!("{label}\t{shortcut}")
+}
+
+fn keystroke_text(keystroke: &Keystroke) -> String {
+    let modifiers = &keystroke.modifiers;
+    let mut parts = Vec::new();
+    if modifiers.control {
+        parts.push("Ctrl".to_string());
+    }
+    if modifiers.alt {
+        parts.push("Alt".to_string());
+    }
+    if modifiers.shift {
+        parts.push("Shift".to_string());
+    }
+    if modifiers.platform {
+        parts.push("Win".to_string());
+    }
+
+    parts.push(key_text(&keystroke.key));
+    parts.join("+")
+}
+
+// Key names as Windows menus spell them in the accelerator column.
+fn key_text(key: &str) -> String {
+    let text = match key {
+        "enter" => "Enter",
+        "escape" => "Esc",
+        "backspace" => "Backspace",
+        "delete" => "Del",
+        "insert" => "Ins",
+        "tab" => "Tab",
+        "space" | " " => "Space",
+        "up" => "Up",
+        "down" => "Down",
+        "left" => "Left",
+        "right" => "Right",
+        "home" => "Home",
+        "end" => "End",
+        "pageup" => "PgUp",
+        "pagedown" => "PgDn",
+        // Letters, digits and punctuation are shown as typed.
+        key if key.chars().count() == 1 => return key.to_uppercase(),
+        // Function keys ("f1" -> "F1") and anything else: capitalize the first letter.
+        key => {
+            let mut chars = key.chars();
+            return chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars).collect())
+                .unwrap_or_default();
+        }
+    };
+    text.to_string()
+}
+
 fn class_name() -> &'static [u16] {
     static NAME: OnceLock<Vec<u16>> = OnceLock::new();
     NAME.get_or_init(|| to_wide_null("GpuiTrayHiddenWindow"))
@@ -198,6 +336,8 @@ unsafe extern "system" fn wndproc(
                 let _ = PostMessageW(hwnd, WM_TRAY_OPEN_MENU, TRAY_CLICK_LEFT_SINGLE, 0);
             } else if event == WM_LBUTTONDBLCLK {
                 let _ = PostMessageW(hwnd, WM_TRAY_OPEN_MENU, TRAY_CLICK_LEFT_DOUBLE, 0);
+            } else if event == WM_MBUTTONUP {
+                let _ = PostMessageW(hwnd, WM_TRAY_OPEN_MENU, TRAY_CLICK_MIDDLE_SINGLE, 0);
             }
             0
         }
@@ -212,10 +352,20 @@ unsafe extern "system" fn wndproc(
             }
             0
         }
-        WM_DESTROY => {
-            PostQuitMessage(0);
+        WM_MENUSELECT => {
+            let flags = ((wparam >> 16) & 0xffff) as u32;
+            // 0xFFFF with a null menu means the menu closed; popups report an index, not an ID.
+            let closed = flags == 0xffff && lparam == 0;
+            if !closed
+                && flags & MF_POPUP == 0
+                && let Some(tray) = tray_from_window(hwnd)
+            {
+                tray.handler.dispatch_hover((wparam & 0xffff) as u16);
+            }
             0
         }
+        // The hidden window is torn down with the tray; it must not end the app's message loop.
+        WM_DESTROY => 0,
         _ => DefWindowProcW(hwnd, message, wparam, lparam),
     }
 }
@@ -263,11 +413,11 @@ pub fn set_up_tray(
     register_window_class(instance)?;
 
     let callback = Arc::new(Mutex::new(Some(on_event)));
-    let id_to_menu_id = Arc::new(Mutex::new(HashMap::new()));
+    let id_to_target = Arc::new(Mutex::new(HashMap::new()));
     let handler = Handler {
         async_app: async_app.clone(),
         callback,
-        id_to_menu_id,
+        id_to_target,
     };
 
     let menu = unsafe { CreatePopupMenu() };
@@ -279,10 +429,14 @@ pub fn set_up_tray(
         handler,
         hwnd: ptr::null_mut(),
         menu,
+        menu_bitmaps: Vec::new(),
+        has_lazy_submenus: false,
         click_policy: TrayClickPolicy::default(),
         icon_added: false,
         hicon: ptr::null_mut(),
         hicon_owned: false,
+        attention_hicon: ptr::null_mut(),
+        blink_on: false,
     });
 
     unsafe {
@@ -306,6 +460,13 @@ pub fn set_up_tray(
         platform.hwnd = hwnd;
     }
 
+    let generation = NEXT_TRAY_GENERATION.fetch_add(1, Ordering::Relaxed);
+    // Without this the icon lingers in the notification area until the user hovers it.
+    let quit_subscription = cx.on_app_quit(move |_| {
+        let _ = remove_runtime(generation);
+        async {}
+    });
+
     TRAY_RUNTIME.with(|runtime_cell| {
         let mut runtime_slot = runtime_cell
             .try_borrow_mut()
@@ -315,24 +476,142 @@ pub fn set_up_tray(
         }
 
         *runtime_slot = Some(TrayRuntime {
+            generation,
             async_app: async_app.clone(),
             state: TrayRuntimeState::new(initial),
             platform: Some(platform),
             interaction_active: false,
+            blink_timer_running: false,
+            _quit_subscription: quit_subscription,
         });
         Ok(())
     })?;
 
-    let handle = TrayHandle;
+    let handle = TrayHandle { generation };
     handle.flush_now(cx)?;
     Ok(handle)
 }
 
+fn is_current_tray(generation: u64) -> bool {
+    TRAY_RUNTIME.with(|runtime_cell| {
+        runtime_cell.try_borrow().is_ok_and(|runtime_slot| {
+            runtime_slot
+                .as_ref()
+                .is_some_and(|runtime| runtime.generation == generation)
+        })
+    })
+}
+
+fn remove_runtime(generation: u64) -> Result<()> {
+    let runtime = TRAY_RUNTIME.with(|runtime_cell| -> Result<Option<TrayRuntime>> {
+        let mut runtime_slot = runtime_cell
+            .try_borrow_mut()
+            .map_err(|_| anyhow::anyhow!("tray runtime already borrowed"))?;
+        let current = runtime_slot
+            .as_ref()
+            .is_some_and(|runtime| runtime.generation == generation);
+        Ok(if current { runtime_slot.take() } else { None })
+    })?;
+
+    // Dropping the platform removes the native item; do it outside the borrow since that can
+    // re-enter the window procedure.
+    drop(runtime);
+    Ok(())
+}
+
+/// Picks up the closures of a state that was set without re-rendering the menu, see
+/// [`TrayRuntimeState::refresh_target`].
+fn refresh_target(target: &mut TrayMenuTarget) {
+    TRAY_RUNTIME.with(|runtime_cell| {
+        if let Ok(runtime_slot) = runtime_cell.try_borrow()
+            && let Some(runtime) = runtime_slot.as_ref()
+        {
+            runtime.state.refresh_target(target);
+        }
+    });
+}
+
+/// Applies [`TrayState::auto_toggle`] for a clicked entry and schedules the resulting flush.
+fn toggle_menu_item(parents: &[String], id: &str) -> Option<bool> {
+    let (checked, async_app) = TRAY_RUNTIME.with(|runtime_cell| {
+        let mut runtime_slot = runtime_cell.try_borrow_mut().ok()?;
+        let runtime = runtime_slot.as_mut()?;
+        let checked = runtime.state.toggle_menu_item(parents, id)?;
+        Some((checked, runtime.async_app.clone()))
+    })?;
+    schedule_flush(async_app);
+    Some(checked)
+}
+
+/// Starts the timer driving [`TrayState::attention_blink`] unless it is already running or
+/// the state doesn't blink.
+fn start_blink_timer(runtime: &mut TrayRuntime) {
+    if runtime.blink_timer_running || !runtime.state.is_blinking() {
+        return;
+    }
+    runtime.blink_timer_running = true;
+
+    let async_app = runtime.async_app.clone();
+    let executor = async_app.foreground_executor().clone();
+    executor
+        .spawn(async move {
+            loop {
+                async_app
+                    .background_executor()
+                    .timer(ATTENTION_BLINK_INTERVAL)
+                    .await;
+                let blinking = TRAY_RUNTIME.with(|runtime_cell| {
+                    // Busy with a flush or click; try again on the next tick.
+                    let Ok(mut runtime_slot) = runtime_cell.try_borrow_mut() else {
+                        return true;
+                    };
+                    let Some(runtime) = runtime_slot.as_mut() else {
+                        return false;
+                    };
+                    let blinking = runtime.state.advance_blink();
+                    runtime.blink_timer_running = blinking;
+                    blinking
+                });
+                if !blinking {
+                    break;
+                }
+                schedule_flush(async_app.clone());
+            }
+        })
+        .detach();
+}
+
 fn schedule_flush(async_app: AsyncApp) {
     async_app
         .foreground_executor()
+        .spawn({
+            let async_app = async_app.clone();
+            async move {
+                async_app.update(|cx| {
+                    let _ = flush_runtime(cx, false);
+                });
+            }
+        })
+        .detach();
+}
+
+/// Runs the providers of lazy submenus in a flush and then shows the menu. `TrackPopupMenu`
+/// runs a modal loop, so it has to wait until the flush has released the app.
+fn populate_and_show_menu(async_app: AsyncApp) {
+    let executor = async_app.foreground_executor().clone();
+    executor
         .spawn(async move {
-            let _ = flush_runtime();
+            let populated = async_app.update(|cx| {
+                let requested = TRAY_RUNTIME.with(|runtime_cell| {
+                    let mut runtime_slot = runtime_cell.try_borrow_mut().ok()?;
+                    runtime_slot.as_mut()?.state.request_flush();
+                    Some(())
+                });
+                requested.is_some() && flush_runtime(cx, true).is_ok()
+            });
+            if populated {
+                let _ = handle_tray_click(TRAY_SHOW_MENU);
+            }
         })
         .detach();
 }
@@ -346,6 +625,8 @@ fn handle_tray_click(click_code: usize) -> Result<()> {
             .as_mut()
             .context("tray has not been initialized")?;
         runtime.interaction_active = true;
+        // The flush restoring the regular icon is scheduled once the click is handled.
+        runtime.state.stop_blink();
         runtime
             .platform
             .take()
@@ -376,7 +657,9 @@ fn handle_tray_click(click_code: usize) -> Result<()> {
     click_result
 }
 
-fn flush_runtime() -> Result<()> {
+/// Applies pending state changes. With `populate_lazy` the providers of lazy submenus run
+/// first; other flushes leave those submenus empty.
+fn flush_runtime(cx: &mut gpui::App, populate_lazy: bool) -> Result<()> {
     loop {
         let step = TRAY_RUNTIME.with(
             |runtime_cell| -> Result<Option<(Box<TrayPlatform>, VersionedTrayState)>> {
@@ -407,8 +690,23 @@ fn flush_runtime() -> Result<()> {
         let Some((mut platform, versioned_state)) = step else {
             return Ok(());
         };
+        // The diff and the applied state stay those of the state the app set; populated lazy
+        // submenus and availability only shape what is shown.
+        let mut diff = TRAY_RUNTIME
+            .with(|runtime_cell| {
+                let runtime_slot = runtime_cell.try_borrow().ok()?;
+                let runtime = runtime_slot.as_ref()?;
+                Some(runtime.state.flush_diff(&versioned_state))
+            })
+            .unwrap_or_else(TrayStateDiff::all);
+        let mut shown_state = versioned_state.state.clone();
+        if populate_lazy {
+            shown_state.populate_lazy_submenus(cx);
+            diff.menu = true;
+        }
+        shown_state.resolve_action_availability(cx);
 
-        let apply_result = unsafe { platform.apply(&versioned_state.state) };
+        let apply_result = unsafe { platform.apply(&shown_state, versioned_state.blink_on, &diff) };
 
         let should_continue = TRAY_RUNTIME.with(|runtime_cell| -> Result<bool> {
             let mut runtime_slot = runtime_cell
@@ -420,6 +718,7 @@ fn flush_runtime() -> Result<()> {
             runtime.platform = Some(platform);
 
             if apply_result.is_ok() {
+                start_blink_timer(runtime);
                 Ok(runtime.state.finish_flush(versioned_state))
             } else {
                 runtime.state.abort_flush();
@@ -441,6 +740,10 @@ impl TrayPlatform {
         let _ = GetCursorPos(&mut point);
 
         let (action, button, kind) = match click_code {
+            TRAY_SHOW_MENU => {
+                self.show_menu(point);
+                return Ok(());
+            }
             TRAY_CLICK_LEFT_SINGLE => (
                 self.click_policy.left,
                 MouseButton::Left,
@@ -456,6 +759,18 @@ impl TrayPlatform {
                 MouseButton::Left,
                 TrayClickKind::Double,
             ),
+            TRAY_CLICK_MIDDLE_SINGLE => {
+                if let Some(id) = self.click_policy.middle_menu_item.as_deref()
+                    && self.handler.dispatch_menu_item(id)
+                {
+                    return Ok(());
+                }
+                (
+                    self.click_policy.middle,
+                    MouseButton::Middle,
+                    TrayClickKind::Single,
+                )
+            }
             _ => return Ok(()),
         };
 
@@ -470,29 +785,37 @@ impl TrayPlatform {
                     },
                 });
             }
-            TrayClickAction::OpenMenu => {
-                let _ = SetForegroundWindow(self.hwnd);
-                let command = TrackPopupMenu(
-                    self.menu,
-                    TPM_LEFTALIGN | TPM_BOTTOMALIGN | TPM_RETURNCMD | TPM_RIGHTBUTTON,
-                    point.x,
-                    point.y,
-                    0,
-                    self.hwnd,
-                    ptr::null(),
-                );
-
-                if command != 0 {
-                    let _ = PostMessageW(self.hwnd, WM_COMMAND, command as usize, 0);
-                }
-                let _ = PostMessageW(self.hwnd, WM_NULL, 0, 0);
+            TrayClickAction::OpenMenu if self.has_lazy_submenus => {
+                populate_and_show_menu(self.handler.async_app.clone());
             }
+            TrayClickAction::OpenMenu => self.show_menu(point),
             TrayClickAction::Ignore => {}
         }
 
         Ok(())
     }
 
+    unsafe fn show_menu(&self, point: WIN_POINT) {
+        // TrackPopupMenu blocks until the menu is dismissed.
+        self.handler.dispatch(TrayEvent::MenuOpened { id: None });
+        let _ = SetForegroundWindow(self.hwnd);
+        let command = TrackPopupMenu(
+            self.menu,
+            TPM_LEFTALIGN | TPM_BOTTOMALIGN | TPM_RETURNCMD | TPM_RIGHTBUTTON,
+            point.x,
+            point.y,
+            0,
+            self.hwnd,
+            ptr::null(),
+        );
+
+        if command != 0 {
+            let _ = PostMessageW(self.hwnd, WM_COMMAND, command as usize, 0);
+        }
+        let _ = PostMessageW(self.hwnd, WM_NULL, 0, 0);
+        self.handler.dispatch(TrayEvent::MenuClosed { id: None });
+    }
+
     unsafe fn notify_data(&self, tooltip: &str) -> NOTIFYICONDATAW {
         let mut data: NOTIFYICONDATAW = mem::zeroed();
         data.cbSize = mem::size_of::<NOTIFYICONDATAW>() as u32;
@@ -501,7 +824,9 @@ impl TrayPlatform {
         data.uFlags = NIF_MESSAGE | NIF_TIP | NIF_ICON;
         data.uCallbackMessage = TRAY_CALLBACK_MESSAGE;
 
-        data.hIcon = if self.hicon != ptr::null_mut() {
+        data.hIcon = if self.blink_on && self.attention_hicon != ptr::null_mut() {
+            self.attention_hicon
+        } else if self.hicon != ptr::null_mut() {
             self.hicon
         } else {
             LoadIconW(ptr::null_mut(), IDI_APPLICATION)
@@ -563,20 +888,16 @@ impl TrayPlatform {
     }
 
     unsafe fn set_icon(&mut self, icon: Option<&gpui::Image>) -> Result<()> {
-        let (width, height, bgra) = match icon {
-            None => {
-                if self.hicon_owned && self.hicon != ptr::null_mut() {
-                    DestroyIcon(self.hicon);
-                }
-                self.hicon = ptr::null_mut();
-                self.hicon_owned = false;
-                return Ok(());
+        let Some(image) = icon else {
+            if self.hicon_owned && self.hicon != ptr::null_mut() {
+                DestroyIcon(self.hicon);
             }
-            Some(image) => crate::icon::decode_gpui_image_to_bgra32(image)
-                .context("failed to decode gpui::Image")?,
+            self.hicon = ptr::null_mut();
+            self.hicon_owned = false;
+            return Ok(());
         };
 
-        let new_hicon = hicon_from_bgra32(width, height, &bgra)?;
+        let new_hicon = hicon_from_image(image)?;
         if self.hicon_owned && self.hicon != ptr::null_mut() {
             DestroyIcon(self.hicon);
         }
@@ -585,37 +906,82 @@ impl TrayPlatform {
         Ok(())
     }
 
+    unsafe fn set_attention_icon(&mut self, icon: Option<&gpui::Image>) -> Result<()> {
+        let new_hicon = match icon {
+            Some(image) => hicon_from_image(image)?,
+            None => ptr::null_mut(),
+        };
+        if self.attention_hicon != ptr::null_mut() {
+            DestroyIcon(self.attention_hicon);
+        }
+        self.attention_hicon = new_hicon;
+        Ok(())
+    }
+
+    unsafe fn destroy_menu_bitmaps(&mut self) {
+        for bitmap in self.menu_bitmaps.drain(..) {
+            let _ = DeleteObject(bitmap);
+        }
+    }
+
     unsafe fn rebuild_menu(&mut self, items: &[TrayMenuItem]) -> Result<()> {
         if self.menu != ptr::null_mut() {
             DestroyMenu(self.menu);
         }
+        // Menus don't own item bitmaps, so they're only safe to free once the menu is gone.
+        self.destroy_menu_bitmaps();
 
         let menu = CreatePopupMenu();
         (menu != ptr::null_mut())
             .then_some(())
             .context("CreatePopupMenu failed")?;
 
-        if let Ok(mut map) = self.handler.id_to_menu_id.lock() {
+        if let Ok(mut map) = self.handler.id_to_target.lock() {
             map.clear();
         }
 
         let mut next_id: u16 = 1000;
         for item in items {
-            append_tray_menu_item(menu, item, &self.handler.id_to_menu_id, &mut next_id)?;
+            append_tray_menu_item(
+                menu,
+                item,
+                &[],
+                &self.handler.id_to_target,
+                &mut next_id,
+                &mut self.menu_bitmaps,
+            )?;
         }
 
         self.menu = menu;
         Ok(())
     }
 
-    unsafe fn apply(&mut self, state: &TrayState) -> Result<()> {
-        self.click_policy = state.click_policy;
-        self.rebuild_menu(&state.submenus)?;
+    unsafe fn apply(
+        &mut self,
+        state: &TrayState,
+        blink_on: bool,
+        diff: &TrayStateDiff,
+    ) -> Result<()> {
+        self.click_policy = state.click_policy.clone();
+        if diff.menu {
+            self.rebuild_menu(&state.submenus)?;
+            self.has_lazy_submenus = has_lazy_submenus(&state.submenus);
+        }
+        if diff.icon {
+            self.set_icon(state.icon.as_deref())?;
+        }
+        if diff.attention {
+            self.set_attention_icon(state.attention_icon.as_deref())?;
+        }
+        self.blink_on = blink_on;
 
         if state.visible {
-            self.set_icon(state.icon.as_deref())?;
+            // NIM_ADD already carries the current icon and tooltip.
+            let newly_added = !self.icon_added;
             self.add_icon(state)?;
-            self.modify_icon(state)?;
+            if !newly_added && (diff.icon || diff.tooltip || diff.blink) {
+                self.modify_icon(state)?;
+            }
         } else {
             self.delete_icon()?;
         }
@@ -624,7 +990,16 @@ impl TrayPlatform {
     }
 }
 
-unsafe fn hicon_from_bgra32(width: u32, height: u32, bgra: &[u8]) -> Result<HICON> {
+fn has_lazy_submenus(items: &[TrayMenuItem]) -> bool {
+    items.iter().any(|item| match item {
+        TrayMenuItem::Submenu {
+            provider, children, ..
+        } => provider.is_some() || has_lazy_submenus(children),
+        TrayMenuItem::Separator { .. } => false,
+    })
+}
+
+unsafe fn dib_from_bgra32(width: u32, height: u32, bgra: &[u8]) -> Result<HBITMAP> {
     let (w, h) = (width as usize, height as usize);
     let expected = w
         .checked_mul(h)
@@ -669,6 +1044,41 @@ unsafe fn hicon_from_bgra32(width: u32, height: u32, bgra: &[u8]) -> Result<HICO
         "CreateDIBSection returned null bits pointer"
     );
     ptr::copy_nonoverlapping(bgra.as_ptr(), bits_ptr.cast::<u8>(), bgra.len());
+    Ok(color_bmp)
+}
+
+unsafe fn menu_bitmap_from_image(image: &gpui::Image) -> Result<HBITMAP> {
+    // Menu item bitmaps are drawn at small icon size and expect premultiplied alpha.
+    const SIZE: u32 = 16;
+
+    let (width, height, bgra) = crate::icon::decode_gpui_image_to_bgra32(image)?;
+    let (scaled_width, scaled_height) = crate::icon::fit_within(width, height, SIZE);
+    let mut bgra = if (scaled_width, scaled_height) != (width, height) {
+        crate::icon::resize_bgra32_nearest(&bgra, width, height, scaled_width, scaled_height)?
+    } else {
+        bgra
+    };
+    let (width, height) = (scaled_width, scaled_height);
+
+    for pixel in bgra.chunks_exact_mut(4) {
+        let alpha = pixel[3] as u32;
+        for channel in &mut pixel[..3] {
+            *channel = (*channel as u32 * alpha / 255) as u8;
+        }
+    }
+
+    dib_from_bgra32(width, height, &bgra)
+}
+
+unsafe fn hicon_from_image(image: &gpui::Image) -> Result<HICON> {
+    let (width, height, bgra) =
+        crate::icon::decode_gpui_image_to_bgra32(image).context("failed to decode gpui::Image")?;
+    hicon_from_bgra32(width, height, &bgra)
+}
+
+unsafe fn hicon_from_bgra32(width: u32, height: u32, bgra: &[u8]) -> Result<HICON> {
+    let (w, h) = (width as usize, height as usize);
+    let color_bmp = dib_from_bgra32(width, height, bgra)?;
 
     let mask_stride = w.div_ceil(32) * 4;
     let mask_bytes = vec![0u8; mask_stride * h];
@@ -699,8 +1109,10 @@ unsafe fn hicon_from_bgra32(width: u32, height: u32, bgra: &[u8]) -> Result<HICO
 unsafe fn append_tray_menu_item(
     menu: HMENU,
     item: &TrayMenuItem,
-    id_to_menu_id: &Arc<Mutex<HashMap<u16, String>>>,
+    parents: &[String],
+    id_to_target: &Arc<Mutex<HashMap<u16, TrayMenuTarget>>>,
     next_id: &mut u16,
+    bitmaps: &mut Vec<HBITMAP>,
 ) -> Result<()> {
     match item {
         TrayMenuItem::Separator { visible, .. } => {
@@ -711,40 +1123,50 @@ unsafe fn append_tray_menu_item(
             if ok == 0 {
                 anyhow::bail!("AppendMenuW(MF_SEPARATOR) failed")
             }
+            if let Some(header) = item.section_header() {
+                append_tray_menu_item(menu, &header, parents, id_to_target, next_id, bitmaps)?;
+            }
         }
         TrayMenuItem::Submenu {
-            id: _,
+            id,
             label,
             enabled,
             visible,
             role: _,
             toggle_type,
+            icon,
+            shortcut,
+            mnemonic,
+            payload: _,
+            on_click: _,
+            action: _,
+            provider: _,
             children,
         } => {
             if !*visible {
                 return Ok(());
             }
 
-            let item_id = item.menu_event_id().map(str::to_owned);
+            let target = item.menu_target(parents);
             if children.is_empty() {
-                let label_w = to_wide_null(label);
+                let label_w = to_wide_null(menu_item_text(
+                    &mnemonic_label(label, *mnemonic, '&'),
+                    shortcut,
+                ));
                 let mut flags = MF_STRING;
-                let checked = match toggle_type {
-                    Some(TrayToggleType::Checkbox(checked)) => *checked,
-                    Some(TrayToggleType::Radio(checked)) => *checked,
-                    None => false,
-                };
+                // Win32 menus have no mixed state, so indeterminate entries show unchecked.
+                let checked = toggle_type.as_ref().is_some_and(TrayToggleType::is_checked);
                 flags |= if checked { MF_CHECKED } else { MF_UNCHECKED };
                 if !*enabled {
                     flags |= MF_DISABLED;
                 }
 
-                let cmd = if let Some(item_id) = item_id {
+                let cmd = if let Some(target) = target {
                     let cmd = *next_id;
                     *next_id = next_id.wrapping_add(1).max(1000);
 
-                    if let Ok(mut map) = id_to_menu_id.lock() {
-                        map.insert(cmd, item_id);
+                    if let Ok(mut map) = id_to_target.lock() {
+                        map.insert(cmd, target);
                     }
                     cmd as usize
                 } else {
@@ -755,16 +1177,26 @@ unsafe fn append_tray_menu_item(
                 if ok == 0 {
                     anyhow::bail!("AppendMenuW(menu item) failed")
                 }
+                set_last_menu_item_icon(menu, icon.as_ref(), bitmaps);
             } else {
                 let submenu = CreatePopupMenu();
                 (submenu != ptr::null_mut())
                     .then_some(())
                     .context("CreatePopupMenu(submenu) failed")?;
+                let mut child_parents = parents.to_vec();
+                child_parents.push(id.clone().unwrap_or_default());
                 for child in children {
-                    append_tray_menu_item(submenu, child, id_to_menu_id, next_id)?;
+                    append_tray_menu_item(
+                        submenu,
+                        child,
+                        &child_parents,
+                        id_to_target,
+                        next_id,
+                        bitmaps,
+                    )?;
                 }
 
-                let label_w = to_wide_null(label);
+                let label_w = to_wide_null(mnemonic_label(label, *mnemonic, '&'));
                 let mut flags = MF_POPUP;
                 if !*enabled {
                     flags |= MF_DISABLED;
@@ -773,9 +1205,55 @@ unsafe fn append_tray_menu_item(
                 if ok == 0 {
                     anyhow::bail!("AppendMenuW(submenu) failed")
                 }
+                set_last_menu_item_icon(menu, icon.as_ref(), bitmaps);
             }
         }
     }
 
     Ok(())
 }
+
+unsafe fn set_last_menu_item_icon(
+    menu: HMENU,
+    icon: Option<&TrayMenuIcon>,
+    bitmaps: &mut Vec<HBITMAP>,
+) {
+    // Themed icon names have no Windows equivalent.
+    let Some(TrayMenuIcon::Image(image)) = icon else {
+        return;
+    };
+    // An icon that fails to decode shouldn't take the whole menu down.
+    let Ok(bitmap) = menu_bitmap_from_image(image) else {
+        return;
+    };
+    bitmaps.push(bitmap);
+
+    let mut info: MENUITEMINFOW = mem::zeroed();
+    info.cbSize = mem::size_of::<MENUITEMINFOW>() as u32;
+    info.fMask = MIIM_BITMAP;
+    info.hbmpItem = bitmap;
+    let position = GetMenuItemCount(menu) - 1;
+    let _ = SetMenuItemInfoW(menu, position as u32, 1, &info);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::key_text;
+
+    #[test]
+    fn key_text_uses_menu_accelerator_names() {
+        for (key, text) in [
+            ("pageup", "PgUp"),
+            ("pagedown", "PgDn"),
+            ("escape", "Esc"),
+            ("delete", "Del"),
+            ("home", "Home"),
+            ("f5", "F5"),
+            ("p", "P"),
+            ("-", "-"),
+            ("/", "/"),
+        ] {
+            assert_eq!(key_text(key), text);
+        }
+    }
+}